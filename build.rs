@@ -0,0 +1,15 @@
+// build.rs
+// Links the kernel with its own linker script
+// tongOS team
+
+// Passed here instead of in .cargo/config, otherwise the user programs of
+// the workspace (user/) would be linked with it too.
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!(
+        "cargo:rustc-link-arg-bins=-T{}/qemu-virt/qemu_virt.lds",
+        manifest_dir
+    );
+    println!("cargo:rerun-if-changed=qemu-virt/qemu_virt.lds");
+}
@@ -0,0 +1,325 @@
+// abi.rs
+// System call ABI shared by the kernel and the user wrappers
+// tongOS team
+
+// A system call is an ecall with the number in a0 and up to five arguments
+// in a1-a5. The result comes back in a0: the value on success or -errno,
+// like Linux, so the last 4095 values of usize are errors.
+// Numbers are stable: new calls get new numbers, old ones never change.
+// This module doesn't depend on the rest of the kernel, the user runtime
+// (user/) builds the same file.
+
+pub const SYS_EXIT: usize = 0;
+pub const SYS_CREATE_THREAD: usize = 1;
+pub const SYS_JOIN: usize = 2;
+pub const SYS_SLEEP: usize = 3;
+pub const SYS_READ_LINE: usize = 4;
+pub const SYS_PRINT_STR: usize = 5;
+pub const SYS_TIME_NOW: usize = 6;
+pub const SYS_GETPID: usize = 7;
+pub const SYS_GETPPID: usize = 8;
+pub const SYS_KILL: usize = 9;
+pub const SYS_SIGACTION: usize = 10;
+pub const SYS_SIGRETURN: usize = 11;
+pub const SYS_SIGPROCMASK: usize = 12;
+pub const SYS_SPAWN: usize = 13;
+pub const SYS_PROCESS_LIST: usize = 14;
+pub const SYS_GETRUSAGE: usize = 15;
+pub const SYS_SCHED_GETPOLICY: usize = 16;
+pub const SYS_SCHED_SETPOLICY: usize = 17;
+pub const SYS_NICE: usize = 18;
+pub const SYS_SCHED_SETPERIODIC: usize = 19;
+pub const SYS_SCHED_WAIT_PERIOD: usize = 20;
+pub const SYS_SCHED_STATS: usize = 21;
+pub const SYS_SCHED_SETAFFINITY: usize = 22;
+pub const SYS_SCHED_GETAFFINITY: usize = 23;
+
+pub const SYSCALL_COUNT: usize = 24;
+
+pub const MAX_ERRNO: usize = 4095;
+
+// A new program starts with sp pointing at argc, followed by argv, NULL,
+// envp, NULL and the auxiliary vector: (type, value) pairs ending with
+// AT_NULL. a0, a1 and a2 hold argc, argv and envp too.
+pub const AT_NULL: usize = 0;
+// Page size
+pub const AT_PAGESZ: usize = 6;
+// Entry point of the program
+pub const AT_ENTRY: usize = 9;
+// Single letter ISA extensions of the hart, bit n is letter 'A' + n
+pub const AT_HWCAP: usize = 16;
+
+// Limits of spawn: number of argv and envp strings together, and their
+// size including the NULs
+pub const MAX_ARGS: usize = 64;
+pub const MAX_ARGS_SIZE: usize = 4096;
+
+// Signals
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGSYS: usize = 31;
+
+pub const NSIG: usize = 32;
+
+// Special handlers for sigaction
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+// sigprocmask operations
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+// Processes killed by a signal exit with 128 + signal
+pub const SIGNAL_EXIT_BASE: usize = 128;
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Errno {
+    // Operation not permitted
+    EPERM = 1,
+    // No such file or directory
+    ENOENT = 2,
+    // No such process
+    ESRCH = 3,
+    // Interrupted system call
+    EINTR = 4,
+    // Argument list too long
+    E2BIG = 7,
+    // Exec format error
+    ENOEXEC = 8,
+    // No child processes
+    ECHILD = 10,
+    // Try again
+    EAGAIN = 11,
+    // Out of memory
+    ENOMEM = 12,
+    // Bad address
+    EFAULT = 14,
+    // Device or resource busy
+    EBUSY = 16,
+    // Invalid argument
+    EINVAL = 22,
+    // Function not implemented
+    ENOSYS = 38,
+}
+
+impl Errno {
+    pub fn from_usize(errno: usize) -> Option<Self> {
+        match errno {
+            1 => Some(Errno::EPERM),
+            2 => Some(Errno::ENOENT),
+            3 => Some(Errno::ESRCH),
+            4 => Some(Errno::EINTR),
+            7 => Some(Errno::E2BIG),
+            8 => Some(Errno::ENOEXEC),
+            10 => Some(Errno::ECHILD),
+            11 => Some(Errno::EAGAIN),
+            12 => Some(Errno::ENOMEM),
+            14 => Some(Errno::EFAULT),
+            16 => Some(Errno::EBUSY),
+            22 => Some(Errno::EINVAL),
+            38 => Some(Errno::ENOSYS),
+            _ => None,
+        }
+    }
+}
+
+pub type SyscallResult = Result<usize, Errno>;
+
+// Value for a0
+pub fn encode(result: SyscallResult) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => (errno as usize).wrapping_neg(),
+    }
+}
+
+// Errnos this side doesn't know are reported as ENOSYS
+pub fn decode(a0: usize) -> SyscallResult {
+    if a0 >= MAX_ERRNO.wrapping_neg() {
+        Err(Errno::from_usize(a0.wrapping_neg()).unwrap_or(Errno::ENOSYS))
+    } else {
+        Ok(a0)
+    }
+}
+
+// Names are cut to this many bytes
+pub const PROCESS_NAME_LEN: usize = 16;
+
+// ProcessInfo::state
+pub const PROCESS_STATE_READY: usize = 0;
+pub const PROCESS_STATE_RUNNING: usize = 1;
+pub const PROCESS_STATE_BLOCKED: usize = 2;
+pub const PROCESS_STATE_SLEEPING: usize = 3;
+pub const PROCESS_STATE_STOPPED: usize = 4;
+pub const PROCESS_STATE_ZOMBIE: usize = 5;
+
+// One process in the snapshot returned by SYS_PROCESS_LIST. Times are in
+// mtime ticks.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub parent: usize,
+    // UTF-8, padded with NULs
+    pub name: [u8; PROCESS_NAME_LEN],
+    pub state: usize,
+    // Hart it runs on, when running
+    pub hart: usize,
+    pub previous_hart: usize,
+    pub created_at: usize,
+    pub cpu_time: usize,
+    // Bytes of its stack and of the program image it runs
+    pub memory: usize,
+    pub nice: isize,
+}
+
+impl ProcessInfo {
+    pub const fn empty() -> Self {
+        ProcessInfo {
+            pid: 0,
+            parent: 0,
+            name: [0; PROCESS_NAME_LEN],
+            state: PROCESS_STATE_READY,
+            hart: 0,
+            previous_hart: 0,
+            created_at: 0,
+            cpu_time: 0,
+            memory: 0,
+            nice: NICE_DEFAULT,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(PROCESS_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    // Cuts name at a character boundary
+    pub fn set_name(&mut self, name: &str) {
+        let mut len = name.len().min(PROCESS_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.name = [0; PROCESS_NAME_LEN];
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            PROCESS_STATE_READY => "ready",
+            PROCESS_STATE_RUNNING => "running",
+            PROCESS_STATE_BLOCKED => "blocked",
+            PROCESS_STATE_SLEEPING => "sleeping",
+            PROCESS_STATE_STOPPED => "stopped",
+            PROCESS_STATE_ZOMBIE => "zombie",
+            _ => "unknown",
+        }
+    }
+}
+
+// What SYS_GETRUSAGE returns. Times are in mtime ticks.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceUsage {
+    // Running its own code
+    pub user_time: usize,
+    // In the kernel on its behalf: traps, syscalls, switching to it
+    pub kernel_time: usize,
+    // Times it got a hart
+    pub context_switches: usize,
+    // Times it got a hart other than the one it last ran on
+    pub migrations: usize,
+    // Jobs completed and deadlines missed, for periodic tasks
+    pub jobs: usize,
+    pub deadline_misses: usize,
+}
+
+impl ResourceUsage {
+    pub const fn new() -> Self {
+        ResourceUsage {
+            user_time: 0,
+            kernel_time: 0,
+            context_switches: 0,
+            migrations: 0,
+            jobs: 0,
+            deadline_misses: 0,
+        }
+    }
+
+    pub fn cpu_time(&self) -> usize {
+        self.user_time + self.kernel_time
+    }
+}
+
+// Scheduling policies, for SYS_SCHED_GETPOLICY and SYS_SCHED_SETPOLICY.
+// The first three and cache affinity run by nice value and differ in the
+// hart a process that becomes ready goes to.
+// An idle hart, or the one with the shortest ready queue
+pub const SCHED_LEAST_BUSY: usize = 0;
+// Every hart in turn
+pub const SCHED_ROUND_ROBIN: usize = 1;
+// The hart after the current one
+pub const SCHED_NEXT_HART: usize = 2;
+// Multi-level feedback queue, harts in turn
+pub const SCHED_MLFQ: usize = 3;
+// Completely fair, by weighted virtual runtime, least busy hart
+pub const SCHED_CFS: usize = 4;
+// The hart it last ran on, unless it is loaded well above the least loaded
+pub const SCHED_CACHE_AFFINITY: usize = 5;
+
+pub const SCHED_POLICY_COUNT: usize = 6;
+
+// Static priority of a process, the lower the nice the sooner it runs and
+// the longer its quantum. Threads and spawned programs inherit it.
+pub const NICE_MIN: isize = -20;
+pub const NICE_MAX: isize = 19;
+pub const NICE_DEFAULT: isize = 0;
+
+// The kernel runs on this many harts at most
+pub const MAX_HARTS: usize = 4;
+
+// Harts a process may run on, bit n for hart n. Threads and spawned
+// programs inherit it.
+pub const AFFINITY_ALL: u64 = (1 << MAX_HARTS) - 1;
+
+// Per hart, what SYS_SCHED_STATS returns. Times are in mtime ticks.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HartStats {
+    // Running the idle process
+    pub idle_time: usize,
+    pub context_switches: usize,
+    // Processes this hart took from others' ready queues, and others took
+    // from its one
+    pub steals: usize,
+    pub stolen: usize,
+    // Processes that ran here after running on another hart
+    pub migrations: usize,
+    // Average run queue length, in LOAD_SCALE fractions of a process
+    pub load: usize,
+}
+
+// A load of one process
+pub const LOAD_SCALE: usize = 1000;
@@ -0,0 +1,193 @@
+// address_space.rs
+// Address space shared by every thread of a program
+// tongOS team
+
+use crate::assembly;
+use crate::cpu;
+use crate::lock::Mutex;
+use crate::page::{self, PageTableEntryFlags, Sv39PageTable};
+use crate::tls::TlsTemplate;
+use crate::trap;
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Guards changes to any address space page table, threads of the same
+// program can map and unmap their stacks from different harts.
+static mut ADDRESS_SPACE_LOCK: Mutex = Mutex::new();
+
+fn get_address_space_lock() -> &'static mut Mutex {
+    unsafe { &mut ADDRESS_SPACE_LOCK }
+}
+
+// Pages unmapped while other harts may still reach them through a stale
+// TLB entry. They are freed once each of those harts has flushed its TLB.
+struct RetiredPages {
+    pages: Vec<*mut u8>,
+    // Root of a dropped page table, its tables go with it
+    page_table: Option<*mut Sv39PageTable>,
+    // Harts that did not flush yet
+    harts: u64,
+}
+
+impl RetiredPages {
+    fn free(self) {
+        for page in self.pages {
+            page::dealloc(page);
+        }
+        if let Some(page_table) = self.page_table {
+            unsafe { (*page_table).unmap() }
+            page::dealloc(page_table as *mut u8);
+        }
+    }
+}
+
+// Guarded by the address space lock
+static mut RETIRED_PAGES: Vec<RetiredPages> = Vec::new();
+
+// This hart flushes right away, the others at their next software
+// interrupt, see flush_tlb_if_requested
+fn retire(pages: Vec<*mut u8>, page_table: Option<*mut Sv39PageTable>) {
+    unsafe { asm!("sfence.vma zero, zero") };
+    let hartid = cpu::get_mhartid();
+    let others = (0..cpu::MAX_HARTS).filter(|other| *other != hartid);
+    let retired = RetiredPages {
+        pages,
+        page_table,
+        harts: others.clone().fold(0, |harts, other| harts | 1 << other),
+    };
+    if retired.harts == 0 {
+        retired.free();
+        return;
+    }
+
+    get_address_space_lock().spin_lock();
+    unsafe { RETIRED_PAGES.push(retired) };
+    get_address_space_lock().unlock();
+    for other in others {
+        trap::send_software_interrupt(other);
+    }
+}
+
+// Software interrupt, before anything runs on this hart. Frees the retired
+// pages no hart can reach anymore.
+pub fn flush_tlb_if_requested() {
+    let hart = 1 << cpu::get_mhartid();
+    get_address_space_lock().spin_lock();
+    let retired = unsafe { &mut RETIRED_PAGES };
+    if retired.iter().any(|retired| retired.harts & hart != 0) {
+        unsafe { asm!("sfence.vma zero, zero") };
+        for retired in retired.iter_mut() {
+            retired.harts &= !hart;
+        }
+        while let Some(position) = retired.iter().position(|retired| retired.harts == 0) {
+            retired.swap_remove(position).free();
+        }
+    }
+    get_address_space_lock().unlock();
+}
+
+// Processes hold it through an Arc: the first thread creates it, every
+// thread created afterwards takes another reference and the page table is
+// freed when the last one is dropped.
+#[derive(Debug)]
+pub struct AddressSpace {
+    page_table: *mut Sv39PageTable,
+    // Pages holding the loaded ELF segments, if any
+    pub image_pages: Vec<*mut u8>,
+    // Copied into every new thread, if the program has thread-locals
+    pub tls: Option<TlsTemplate>,
+    pub asid: usize,
+}
+
+unsafe impl Send for AddressSpace {}
+unsafe impl Sync for AddressSpace {}
+
+impl AddressSpace {
+    pub fn new(asid: usize) -> Self {
+        let page_table = page::zalloc(1) as *mut Sv39PageTable;
+        assert!(!page_table.is_null());
+
+        AddressSpace {
+            page_table,
+            image_pages: Vec::new(),
+            tls: None,
+            asid,
+        }
+    }
+
+    // Address space of processes linked into the kernel: text, rodata,
+    // data, bss and heap are identity mapped.
+    pub fn new_kernel_image(asid: usize) -> Arc<Self> {
+        use PageTableEntryFlags::{UserRead, UserReadExecute, UserReadWrite};
+
+        let mut address_space = AddressSpace::new(asid);
+        address_space.tls = TlsTemplate::kernel_image();
+        unsafe {
+            address_space.map_identity(assembly::TEXT_START, assembly::TEXT_END, UserReadExecute);
+            address_space.map_identity(assembly::RODATA_START, assembly::RODATA_END, UserRead);
+            address_space.map_identity(assembly::DATA_START, assembly::DATA_END, UserReadWrite);
+            address_space.map_identity(assembly::BSS_START, assembly::BSS_END, UserReadWrite);
+            address_space.map_identity(
+                assembly::HEAP_START,
+                assembly::HEAP_START + assembly::HEAP_SIZE,
+                UserReadWrite,
+            );
+        }
+        Arc::new(address_space)
+    }
+
+    pub fn page_table(&self) -> &Sv39PageTable {
+        unsafe { &*self.page_table }
+    }
+
+    // Only for address spaces that are not shared yet (e.g. while loading)
+    pub fn page_table_mut(&mut self) -> &mut Sv39PageTable {
+        unsafe { &mut *self.page_table }
+    }
+
+    pub fn satp(&self) -> usize {
+        cpu::build_satp(self.asid, self.page_table as usize)
+    }
+
+    // Identity maps every page touched by [start, end)
+    pub fn map_identity(&self, start: usize, end: usize, flags: PageTableEntryFlags) {
+        let start = page::align_address_down(start, page::PAGE_ORDER);
+        let flags = flags as usize;
+
+        get_address_space_lock().spin_lock();
+        for address in (start..end).step_by(page::PAGE_SIZE) {
+            unsafe { (*self.page_table).map(address, address, flags, 0) };
+        }
+        get_address_space_lock().unlock();
+    }
+
+    // Removes the mappings of every page touched by [start, end), the
+    // physical pages are not freed.
+    pub fn unmap(&self, start: usize, end: usize) {
+        let start = page::align_address_down(start, page::PAGE_ORDER);
+
+        get_address_space_lock().spin_lock();
+        for address in (start..end).step_by(page::PAGE_SIZE) {
+            unsafe { (*self.page_table).unmap_page(address) };
+        }
+        get_address_space_lock().unlock();
+    }
+
+    // Unmaps the stack of a thread that exits, its siblings may still reach
+    // it through their TLB for a while
+    pub fn retire_stack(&self, stack: *mut u8, size: usize) {
+        let start = stack as usize;
+        self.unmap(start, start + size);
+        retire(vec![stack], None);
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        debug!("drop address space: {}", self.asid);
+        let image_pages = core::mem::replace(&mut self.image_pages, Vec::new());
+        retire(image_pages, Some(self.page_table));
+    }
+}
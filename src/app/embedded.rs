@@ -0,0 +1,24 @@
+// User programs built as separate ELF binaries (see app/elf, user/ and
+// `make user_programs`) and embedded in the kernel image.
+
+pub static HELLO: &[u8] = include_bytes!("elf/hello.elf");
+// Written in Rust against the user runtime, user/src/bin/workers.rs
+pub static WORKERS: &[u8] = include_bytes!("elf/workers.elf");
+// Spawns the other programs with arguments and environment
+pub static LAUNCHER: &[u8] = include_bytes!("elf/launcher.elf");
+// Lists the processes, user/src/bin/ps.rs
+pub static PS: &[u8] = include_bytes!("elf/ps.elf");
+
+pub static PROGRAMS: &[(&str, &[u8])] = &[
+    ("hello", HELLO),
+    ("workers", WORKERS),
+    ("launcher", LAUNCHER),
+    ("ps", PS),
+];
+
+pub fn find(name: &str) -> Option<&'static [u8]> {
+    PROGRAMS
+        .iter()
+        .find(|(program, _)| *program == name)
+        .map(|(_, image)| *image)
+}
@@ -0,0 +1,58 @@
+use crate::process;
+use alloc::format;
+use alloc::vec::Vec;
+
+const NUM_THREADS: usize = 4;
+const ITERATIONS: usize = 200_000;
+// Sleep every so often so the threads interleave even without preemption
+const SLEEP_EVERY: usize = 20_000;
+
+// Every step is a power of two fraction, so the sums are exact and any
+// register corrupted by another thread shows up as a wrong result.
+fn accumulate(n: usize) {
+    let step = (n + 1) as f64 * 0.25;
+    let mut sum = 0.0f64;
+    let mut half_sum = 0.0f64;
+    let mut small_sum = 0.0f32;
+
+    for i in 1..=ITERATIONS {
+        sum += step;
+        half_sum += step * 0.5;
+        small_sum += 0.125;
+        if i % SLEEP_EVERY == 0 {
+            process::sleep(1);
+        }
+    }
+
+    let expected = step * ITERATIONS as f64;
+    let ok =
+        sum == expected && half_sum == expected * 0.5 && small_sum == 0.125 * ITERATIONS as f32;
+    process::print_str(&format!(
+        "float thread {}: sum {} half {} small {} (expected {}) {}",
+        n,
+        sum,
+        half_sum,
+        small_sum,
+        expected,
+        if ok { "ok" } else { "CORRUPTED" }
+    ));
+    process::exit(if ok { 0 } else { 1 });
+}
+
+pub fn main() {
+    let threads: Vec<usize> = (0..NUM_THREADS)
+        .map(|n| process::create_thread(accumulate as usize, n, 0, 0).unwrap())
+        .collect();
+
+    let mut failures = 0;
+    for pid in threads {
+        if process::join(pid) != Ok(0) {
+            failures += 1;
+        }
+    }
+    process::print_str(&format!(
+        "floating point test: {} of {} threads corrupted",
+        failures, NUM_THREADS
+    ));
+    process::exit(failures);
+}
@@ -21,5 +21,5 @@ pub unsafe fn main() {
     process::print_str("I'm going to sleep now!");
     process::sleep(age as usize);
     process::print_str("I'm back.");
-    process::exit();
+    process::exit(0);
 }
@@ -1,2 +1,7 @@
+pub mod embedded;
 pub mod philosopher;
-pub mod input_example;
\ No newline at end of file
+pub mod input_example;
+pub mod signal_example;
+pub mod float_example;
+pub mod vector_example;
+pub mod realtime_example;
\ No newline at end of file
@@ -1,14 +1,23 @@
+use crate::abi::{HartStats, LOAD_SCALE};
 use crate::lock::Mutex;
 use crate::process;
 use alloc::format;
+use alloc::vec::Vec;
 
 const ITERATIONS: isize = 3;
 const NUM_PHILOSOPHERS: usize = 5;
 const SLEEP_TIME: usize = 500;
+// Below the default priority, so interactive processes go first
+const PHILOSOPHER_NICE: isize = 10;
+
+// Every philosopher thread counts its own meals
+#[thread_local]
+static mut MEALS: usize = 0;
 
 pub unsafe fn philosopher_dinner(n: usize, table: *mut Mutex, chopstick: *mut Mutex) {
     let chopstick = core::slice::from_raw_parts_mut(chopstick, NUM_PHILOSOPHERS);
     let table = &mut (*table);
+    process::nice(0, PHILOSOPHER_NICE).unwrap();
 
     let first = if n < (NUM_PHILOSOPHERS - 1) { n } else { 0 };
     let second = if n < NUM_PHILOSOPHERS - 1 {
@@ -17,6 +26,15 @@ pub unsafe fn philosopher_dinner(n: usize, table: *mut Mutex, chopstick: *mut Mu
         NUM_PHILOSOPHERS - 1
     };
 
+    table.spin_lock();
+    process::print_str(&format!(
+        "Philosopher {} sits down: pid {}, parent {}",
+        n,
+        process::getpid(),
+        process::getppid()
+    ));
+    table.unlock();
+
     for i in (0..=ITERATIONS).rev() {
         table.spin_lock();
         process::print_str(&format!("Philosopher {} is thinking. Iteration={}", n, i));
@@ -36,6 +54,7 @@ pub unsafe fn philosopher_dinner(n: usize, table: *mut Mutex, chopstick: *mut Mu
         table.unlock();
 
         process::sleep(SLEEP_TIME);
+        MEALS += 1;
 
         table.spin_lock();
         process::print_str(&format!("Philosopher {} is sate. Iteration={}", n, i));
@@ -45,15 +64,54 @@ pub unsafe fn philosopher_dinner(n: usize, table: *mut Mutex, chopstick: *mut Mu
         chopstick[second as usize].unlock();
     }
 
+    let usage = process::getrusage(0).unwrap();
     table.spin_lock();
-    process::print_str(&format!("Philosopher {} is done!", { n }));
+    process::print_str(&format!(
+        "Philosopher {} is done! user {} kernel {} ticks, {} switches, {} migrations",
+        n, usage.user_time, usage.kernel_time, usage.context_switches, usage.migrations
+    ));
     table.unlock();
 
-    process::exit();
+    // The number of meals is our exit code, main gets it back from join
+    process::exit(MEALS);
+}
+
+// Busy share and migrations of every hart during the dinner, and how even
+// it was
+fn print_balance(start: &[HartStats], end: &[HartStats], elapsed: usize) {
+    let mut busy_shares = Vec::new();
+    let mut migrations = 0;
+    for (hartid, (start, end)) in start.iter().zip(end).enumerate() {
+        let idle = end.idle_time - start.idle_time;
+        let busy = elapsed.saturating_sub(idle) * 100 / elapsed.max(1);
+        busy_shares.push(busy);
+        migrations += end.migrations - start.migrations;
+        process::print_str(&format!(
+            "hart {}: busy {}%, load {}.{:02}, {} context switches, {} migrations, stole {}, stolen {}",
+            hartid,
+            busy,
+            end.load / LOAD_SCALE,
+            end.load % LOAD_SCALE * 100 / LOAD_SCALE,
+            end.context_switches - start.context_switches,
+            end.migrations - start.migrations,
+            end.steals - start.steals,
+            end.stolen - start.stolen
+        ));
+    }
+    let most = busy_shares.iter().max().copied().unwrap_or(0);
+    let least = busy_shares.iter().min().copied().unwrap_or(0);
+    process::print_str(&format!(
+        "balance: least busy hart {}%, busiest {}%, {} migrations with policy {}",
+        least,
+        most,
+        migrations,
+        process::sched_getpolicy()
+    ));
 }
 
 pub fn main() {
     let start_time = process::time_now();
+    let start_stats = process::sched_stats().unwrap();
     let mut table = Mutex::new();
     let mut chopstick: [Mutex; NUM_PHILOSOPHERS as usize] =
         [Mutex::new(); NUM_PHILOSOPHERS as usize];
@@ -71,7 +129,8 @@ pub fn main() {
             i as usize,
             &mut table as *mut _ as usize,
             (&mut chopstick).as_mut_ptr() as usize,
-        );
+        )
+        .unwrap();
     }
 
     process::print_str("Philosophers are alive and hungry!");
@@ -81,18 +140,23 @@ pub fn main() {
 
     for i in 0..NUM_PHILOSOPHERS {
         let pid = philosopher[i as usize];
-        process::join(pid);
+        let meals = process::join(pid);
 
         table.spin_lock();
-        process::print_str(&format!("Philosopher {} ate {} times!", i, ITERATIONS));
+        match meals {
+            Ok(meals) => process::print_str(&format!("Philosopher {} ate {} times!", i, meals)),
+            Err(_) => process::print_str(&format!("Philosopher {} is gone!", i)),
+        }
         table.unlock();
     }
 
-    let time = process::time_now() - start_time;
-    let time = time / crate::cpu::FREQ as usize;
+    let elapsed = process::time_now() - start_time;
+    print_balance(&start_stats, &process::sched_stats().unwrap(), elapsed);
+
+    let time = elapsed / crate::cpu::FREQ as usize;
     process::print_str(&format!(
         "Finished philosophers dinner! time elapsed {} seconds.",
         time
     ));
-    process::exit();
+    process::exit(0);
 }
@@ -0,0 +1,73 @@
+use crate::cpu;
+use crate::process;
+use alloc::format;
+use alloc::vec::Vec;
+
+const JOBS: usize = 10;
+
+// (name, period, wcet, deadline, work), in context switch periods. Work is
+// the CPU time a job really takes: "overrun" declares less than it uses and
+// misses deadlines. The four harts can't take them all, one is rejected.
+const TASKS: [(&str, usize, usize, usize, usize); 6] = [
+    ("fast", 5, 2, 5, 1),
+    ("slow", 10, 3, 8, 2),
+    ("overrun", 6, 2, 3, 4),
+    ("heavy", 10, 6, 10, 5),
+    ("heavy", 10, 6, 10, 5),
+    ("heavy", 10, 6, 10, 5),
+];
+
+fn cpu_time() -> usize {
+    process::getrusage(0).unwrap().user_time
+}
+
+fn periodic_task(index: usize) {
+    let (name, period, wcet, deadline, work) = TASKS[index];
+    let hart = match process::sched_setperiodic(period, wcet, deadline) {
+        Ok(hart) => hart,
+        Err(errno) => {
+            process::print_str(&format!(
+                "rt {} ({}): not admitted, {:?}",
+                index, name, errno
+            ));
+            process::exit(1);
+        }
+    };
+    process::print_str(&format!(
+        "rt {} ({}): admitted on hart {}",
+        index, name, hart
+    ));
+
+    let work = work * cpu::CONTEXT_SWITCH_TIME as usize;
+    for _ in 0..JOBS {
+        let start = cpu_time();
+        while cpu_time() - start < work {}
+        process::sched_wait_period();
+    }
+
+    let usage = process::getrusage(0).unwrap();
+    process::print_str(&format!(
+        "rt {} ({}): {} jobs, {} deadline misses",
+        index, name, usage.jobs, usage.deadline_misses
+    ));
+    process::exit(0);
+}
+
+pub fn main() {
+    let tasks: Vec<usize> = (0..TASKS.len())
+        .map(|index| process::create_thread(periodic_task as usize, index, 0, 0).unwrap())
+        .collect();
+
+    let mut admitted = 0;
+    for pid in tasks {
+        if process::join(pid) == Ok(0) {
+            admitted += 1;
+        }
+    }
+    process::print_str(&format!(
+        "real-time example: {} of {} tasks admitted",
+        admitted,
+        TASKS.len()
+    ));
+    process::exit(0);
+}
@@ -0,0 +1,83 @@
+use crate::process;
+use crate::signal;
+use alloc::format;
+
+static mut USR1_RECEIVED: usize = 0;
+
+extern "C" fn on_usr1(sig: usize) {
+    unsafe {
+        USR1_RECEIVED += 1;
+    }
+    process::print_str(&format!(
+        "worker {}: caught signal {}",
+        process::getpid(),
+        sig
+    ));
+}
+
+fn worker() {
+    process::sigaction(signal::SIGUSR1, on_usr1 as usize).unwrap();
+    process::sigaction(signal::SIGINT, signal::SIG_IGN).unwrap();
+
+    while unsafe { USR1_RECEIVED } < 2 {
+        process::sleep(5);
+    }
+
+    process::print_str("worker: waiting to be terminated");
+    loop {
+        process::sleep(5);
+    }
+}
+
+fn faulty() {
+    process::print_str("faulty: writing through a null pointer");
+    unsafe { (0 as *mut usize).write_volatile(42) };
+    process::exit(0);
+}
+
+fn sleeper() {
+    loop {
+        process::print_str("sleeper: tick");
+        process::sleep(5);
+    }
+}
+
+pub fn main() {
+    let worker = process::create_thread(worker as usize, 0, 0, 0).unwrap();
+    process::sleep(10);
+
+    // Ignored by the worker
+    let _ = process::kill(worker, signal::SIGINT);
+    let _ = process::kill(worker, signal::SIGUSR1);
+    process::sleep(10);
+    let _ = process::kill(worker, signal::SIGUSR1);
+    process::sleep(10);
+
+    let _ = process::kill(worker, signal::SIGTERM);
+    if let Ok(code) = process::join(worker) {
+        process::print_str(&format!("worker exited with {}", code));
+    }
+
+    let faulty = process::create_thread(faulty as usize, 0, 0, 0).unwrap();
+    if let Ok(code) = process::join(faulty) {
+        process::print_str(&format!("faulty exited with {}", code));
+    }
+
+    // No ticks while suspended
+    let sleeper = process::create_thread(sleeper as usize, 0, 0, 0).unwrap();
+    process::sleep(12);
+    process::suspend(sleeper).unwrap();
+    process::print_str("sleeper suspended");
+    process::sleep(20);
+    process::print_str("resuming sleeper");
+    process::resume(sleeper).unwrap();
+    process::sleep(12);
+
+    // Interrupts its sleep
+    process::kill(sleeper, signal::SIGKILL).unwrap();
+    if let Ok(code) = process::join(sleeper) {
+        process::print_str(&format!("sleeper exited with {}", code));
+    }
+
+    process::exit(0);
+}
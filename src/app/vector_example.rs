@@ -0,0 +1,123 @@
+use crate::process;
+use crate::signal;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const NUM_THREADS: usize = 4;
+const LENGTH: usize = 1000;
+const ROUNDS: usize = 50;
+
+// The vector instructions are hand encoded, see vector.rs
+
+// c[i] = a[i] + b[i] for the next strip of at most n elements.
+// Returns how many elements were added (vl).
+unsafe fn add_strip(a: *const u64, b: *const u64, c: *mut u64, n: usize) -> usize {
+    let vl: usize;
+    asm!(
+        ".word 0x0d8572d7   # vsetvli t0, a0, e64, m1, ta, ma
+         .word 0x0205f087   # vle64.v v1, (a1)
+         .word 0x02067107   # vle64.v v2, (a2)
+         .word 0x021101d7   # vadd.vv v3, v1, v2
+         .word 0x0206f1a7   # vse64.v v3, (a3)",
+        in("a0") n,
+        in("a1") a,
+        in("a2") b,
+        in("a3") c,
+        out("t0") vl,
+    );
+    vl
+}
+
+// v4 = [value; VLMAX], returns VLMAX
+unsafe fn splat(value: usize) -> usize {
+    let vl: usize;
+    asm!(
+        ".word 0x0d8072d7   # vsetvli t0, zero, e64, m1, ta, ma
+         .word 0x5e054257   # vmv.v.x v4, a0",
+        in("a0") value,
+        out("t0") vl,
+    );
+    vl
+}
+
+// v4 += value, with the vl and vtype set by splat
+unsafe fn accumulate(value: usize) {
+    asm!(
+        ".word 0x02454257   # vadd.vx v4, v4, a0",
+        in("a0") value,
+    );
+}
+
+// Stores vl elements of v4 at out
+unsafe fn store(out: *mut u64) {
+    asm!(
+        ".word 0x0205f227   # vse64.v v4, (a1)",
+        in("a1") out,
+    );
+}
+
+fn vector_worker(n: usize) {
+    let step = n + 1;
+
+    // Strip mined loop, vl decides how much each iteration does
+    let a: Vec<u64> = (0..LENGTH as u64).collect();
+    let b: Vec<u64> = (0..LENGTH as u64).map(|x| x * step as u64).collect();
+    let mut c = vec![0u64; LENGTH];
+    let mut i = 0;
+    while i < LENGTH {
+        i += unsafe {
+            add_strip(
+                a.as_ptr().add(i),
+                b.as_ptr().add(i),
+                c.as_mut_ptr().add(i),
+                LENGTH - i,
+            )
+        };
+    }
+    let add_ok = (0..LENGTH).all(|i| c[i] == a[i] + b[i]);
+
+    // v4, vl and vtype must survive every sleep, possibly moving to
+    // another hart
+    let vl = unsafe { splat(step) };
+    for _ in 0..ROUNDS {
+        unsafe { accumulate(step) };
+        process::sleep(1);
+    }
+    let mut lanes = vec![0u64; vl];
+    unsafe { store(lanes.as_mut_ptr()) };
+    let expected = (step * (ROUNDS + 1)) as u64;
+    let kept_ok = lanes.iter().all(|lane| *lane == expected);
+
+    process::print_str(&format!(
+        "vector thread {}: vl {}, add {}, registers {}",
+        n,
+        vl,
+        if add_ok { "ok" } else { "WRONG" },
+        if kept_ok { "kept" } else { "CORRUPTED" }
+    ));
+    process::exit(if add_ok && kept_ok { 0 } else { 1 });
+}
+
+pub fn main() {
+    let threads: Vec<usize> = (0..NUM_THREADS)
+        .map(|n| process::create_thread(vector_worker as usize, n, 0, 0).unwrap())
+        .collect();
+
+    let mut failures = 0;
+    for pid in threads {
+        match process::join(pid) {
+            Ok(0) => {}
+            Ok(code) if code == signal::SIGNAL_EXIT_BASE + signal::SIGILL => {
+                process::print_str("no vector extension, run qemu with -cpu rv64,v=true");
+                failures += 1;
+            }
+            _ => failures += 1,
+        }
+    }
+    process::print_str(&format!(
+        "vector test: {} of {} threads failed",
+        failures, NUM_THREADS
+    ));
+    process::exit(failures);
+}
@@ -3,12 +3,23 @@ global_asm!(include_str!("memory.S"));
 global_asm!(include_str!("trap.S"));
 
 use crate::cpu::TrapFrame;
+use crate::fpu::FloatingPointState;
 
 extern "C" {
     #[allow(improper_ctypes)]
     pub fn __tong_os_switch_to_process(process: *const TrapFrame) -> !;
 
     pub fn __tong_os_trap_machine_mode() -> !;
+
+    pub fn __tong_os_signal_restorer() -> !;
+
+    pub fn __tong_os_fp_save(state: *mut FloatingPointState);
+
+    pub fn __tong_os_fp_restore(state: *const FloatingPointState);
+
+    pub fn __tong_os_vector_save(registers: *mut u8, csrs: *mut usize);
+
+    pub fn __tong_os_vector_restore(registers: *const u8, csrs: *const usize);
 }
 
 extern "C" {
@@ -22,6 +33,9 @@ extern "C" {
     pub static DATA_END: usize;
     pub static RODATA_START: usize;
     pub static RODATA_END: usize;
+    pub static TDATA_START: usize;
+    pub static TDATA_END: usize;
+    pub static TBSS_END: usize;
     pub static KERNEL_STACK_START: usize;
     pub static KERNEL_STACK_END: usize;
 }
@@ -52,7 +52,7 @@ pub fn example_process1(test: usize) -> () {
     process::print_str(&format!("Arg: {}", test));
 
     process::print_str("exiting process");
-    process::exit();
+    process::exit(0);
 }
 
 pub fn example_process2() -> () {
@@ -61,7 +61,7 @@ pub fn example_process2() -> () {
     // some_math(100);
 
     process::print_str("exiting process");
-    process::exit();
+    process::exit(0);
 }
 
 pub fn example_process3(iteration: usize) {
@@ -78,33 +78,121 @@ pub fn example_process3(iteration: usize) {
         "Ex3 counter = {}. Expected = {}",
         my_counter, iteration
     ));
-    process::exit();
+    process::exit(0);
 }
 
 pub fn choose_processes(process_to_run: usize) {
     match process_to_run {
         1 => {
-            let process = process::Process::new(example_process1 as usize, 666, 0, 0);
+            let process = process::Process::new("example1", example_process1 as usize, 666, 0, 0);
             process::process_list_add(process);
-            let process = process::Process::new(example_process2 as usize, 0, 0, 0);
+            let process = process::Process::new("example2", example_process2 as usize, 0, 0, 0);
             process::process_list_add(process);
-            let process = process::Process::new(example_process3 as usize, 666, 0, 0);
+            let process = process::Process::new("example3", example_process3 as usize, 666, 0, 0);
             process::process_list_add(process);
-            let process = process::Process::new(example_process3 as usize, 42, 0, 0);
+            let process = process::Process::new("example3", example_process3 as usize, 42, 0, 0);
             process::process_list_add(process);
         }
         2 => {
-            let process = process::Process::new(crate::app::philosopher::main as usize, 0, 0, 0);
+            let process = process::Process::new(
+                "philosopher",
+                crate::app::philosopher::main as usize,
+                0,
+                0,
+                0,
+            );
             process::process_list_add(process);
         }
         3 => {
-            let process = process::Process::new(crate::app::input_example::main as usize, 0, 0, 0);
+            let process = process::Process::new(
+                "input_example",
+                crate::app::input_example::main as usize,
+                0,
+                0,
+                0,
+            );
             process::process_list_add(process);
         }
         4 => {
             choose_processes(1);
             choose_processes(3);
             choose_processes(2);
+            choose_processes(5);
+            choose_processes(6);
+            choose_processes(7);
+            choose_processes(8);
+            choose_processes(9);
+            choose_processes(10);
+            choose_processes(11);
+        }
+        5 => match process::Process::new_from_elf(
+            "hello",
+            crate::app::embedded::HELLO,
+            &["hello"],
+            &[],
+        ) {
+            Ok(process) => process::process_list_add(process),
+            Err(error) => println!("Could not load hello: {:?}", error),
+        },
+        6 => {
+            let process = process::Process::new(
+                "signal_example",
+                crate::app::signal_example::main as usize,
+                0,
+                0,
+                0,
+            );
+            process::process_list_add(process);
+        }
+        7 => {
+            let process = process::Process::new(
+                "float_example",
+                crate::app::float_example::main as usize,
+                0,
+                0,
+                0,
+            );
+            process::process_list_add(process);
+        }
+        8 => {
+            let process = process::Process::new(
+                "vector_example",
+                crate::app::vector_example::main as usize,
+                0,
+                0,
+                0,
+            );
+            process::process_list_add(process);
+        }
+        9 => match process::Process::new_from_elf(
+            "workers",
+            crate::app::embedded::WORKERS,
+            &["workers"],
+            &["WORKERS=4"],
+        ) {
+            Ok(process) => process::process_list_add(process),
+            Err(error) => println!("Could not load workers: {:?}", error),
+        },
+        10 => {
+            match process::Process::new_from_elf(
+                "launcher",
+                crate::app::embedded::LAUNCHER,
+                &["launcher"],
+                &[],
+            ) {
+                Ok(process) => process::process_list_add(process),
+                Err(error) => println!("Could not load launcher: {:?}", error),
+            }
+        }
+        11 => {
+            let process = process::Process::new(
+                "realtime",
+                crate::app::realtime_example::main as usize,
+                0,
+                0,
+                0,
+            );
+            process::process_list_add(process);
         }
         _ => {
             println!("Process not found!");
@@ -0,0 +1,164 @@
+// console.rs
+// Line oriented keyboard input from the UART
+// tongOS team
+
+// The UART interrupt echoes what is typed and collects it into a line.
+// When Enter is pressed the line goes to the process blocked in read_line,
+// copied into its buffer through its own page table, or is kept until a
+// process asks for it. One reader at a time.
+// The PLIC is only configured for hart 0, so keyboard interrupts are always
+// taken there.
+
+use crate::abi::{Errno, SyscallResult};
+use crate::lock::Mutex;
+use crate::page::Sv39PageTable;
+use crate::plic;
+use crate::process;
+use crate::user_memory::{self, Access};
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+const UART_INTERRUPT: u32 = 10;
+
+// Lines typed while nobody is reading, the oldest are dropped
+const MAX_PENDING_LINES: usize = 16;
+
+struct Reader {
+    pid: usize,
+    buffer: usize,
+    capacity: usize,
+}
+
+static mut CONSOLE_LOCK: Mutex = Mutex::new();
+static mut LINE: Vec<u8> = Vec::new();
+static mut COMPLETED_LINES: Option<VecDeque<Vec<u8>>> = None;
+static mut READER: Option<Reader> = None;
+
+pub enum ReadResult {
+    Done(SyscallResult),
+    // The caller is in the blocked list, its a0 is written when a line
+    // arrives
+    Blocked,
+}
+
+fn get_console_lock() -> &'static mut Mutex {
+    unsafe { &mut CONSOLE_LOCK }
+}
+
+fn completed_lines_mut() -> &'static mut VecDeque<Vec<u8>> {
+    unsafe { COMPLETED_LINES.get_or_insert_with(VecDeque::new) }
+}
+
+// Hart 0, before any process runs
+pub fn init() {
+    plic::set_threshold(6);
+    plic::set_priority(UART_INTERRUPT, 7);
+    plic::enable(UART_INTERRUPT);
+}
+
+// Copies the line into the user buffer, cutting it at capacity. Returns the
+// number of bytes copied.
+fn deliver(
+    page_table: &Sv39PageTable,
+    buffer: usize,
+    capacity: usize,
+    line: &[u8],
+) -> SyscallResult {
+    let len = line.len().min(capacity);
+    user_memory::copy_to_user(page_table, buffer, &line[..len])?;
+    Ok(len)
+}
+
+// Called by the running process. Blocks it when there is no line yet.
+pub fn read_line(buffer: usize, capacity: usize) -> ReadResult {
+    let page_table = match user_memory::running_page_table() {
+        Ok(page_table) => page_table,
+        Err(errno) => return ReadResult::Done(Err(errno)),
+    };
+    let capacity = capacity.min(user_memory::MAX_COPY_SIZE);
+    if let Err(errno) = user_memory::check_user_range(page_table, buffer, capacity, Access::Write) {
+        return ReadResult::Done(Err(errno));
+    }
+
+    get_console_lock().spin_lock();
+    let result = if let Some(line) = completed_lines_mut().pop_front() {
+        ReadResult::Done(deliver(page_table, buffer, capacity, &line))
+    } else if unsafe { READER.is_some() } {
+        ReadResult::Done(Err(Errno::EAGAIN))
+    } else {
+        unsafe {
+            READER = Some(Reader {
+                pid: process::get_running_process_pid(),
+                buffer,
+                capacity,
+            });
+        }
+        // Still holding the console lock, so the line can't arrive before
+        // the reader is in the blocked list
+        process::block_process();
+        ReadResult::Blocked
+    };
+    get_console_lock().unlock();
+    result
+}
+
+// A signal interrupts pid: runs interrupt with the console locked, so no
+// line can be handed to pid meanwhile, and forgets pid as the reader if it
+// was interrupted. Returns what interrupt returned.
+pub fn cancel_read<F: FnOnce() -> bool>(pid: usize, interrupt: F) -> bool {
+    get_console_lock().spin_lock();
+    let interrupted = interrupt();
+    unsafe {
+        if interrupted && READER.as_ref().map_or(false, |reader| reader.pid == pid) {
+            READER = None;
+        }
+    }
+    get_console_lock().unlock();
+    interrupted
+}
+
+// UART interrupt. Returns true if a process was woken up.
+pub fn handle_input(c: u8) -> bool {
+    get_console_lock().spin_lock();
+    let line = unsafe { &mut LINE };
+    let mut woken = false;
+
+    match c {
+        // backspace
+        8 | 127 => {
+            if line.pop().is_some() {
+                print!("{0} {0}", 8 as char);
+            }
+        }
+        // Enter
+        10 | 13 => {
+            println!("");
+            let line = core::mem::replace(line, Vec::new());
+            match unsafe { READER.take() } {
+                Some(reader) => {
+                    process::unblock_process_with(reader.pid, |process| {
+                        let page_table = process.page_table().ok_or(Errno::EFAULT)?;
+                        deliver(page_table, reader.buffer, reader.capacity, &line)
+                    });
+                    woken = true;
+                }
+                None => {
+                    let completed = completed_lines_mut();
+                    if completed.len() == MAX_PENDING_LINES {
+                        completed.pop_front();
+                    }
+                    completed.push_back(line);
+                }
+            }
+        }
+        // Char
+        _ => {
+            print!("{}", c as char);
+            line.push(c);
+        }
+    }
+
+    get_console_lock().unlock();
+    woken
+}
@@ -8,6 +8,8 @@
 pub const FREQ: u64 = 10_000_000;
 // Let's do this 250 times per second for switching
 pub const CONTEXT_SWITCH_TIME: u64 = FREQ / 500;
+// Per-hart arrays are sized by it
+pub use crate::abi::MAX_HARTS;
 
 #[repr(usize)]
 pub enum CpuMode {
@@ -162,6 +164,12 @@ pub fn get_mstatus() -> usize {
     }
 }
 
+pub fn get_misa() -> usize {
+    let misa: usize;
+    unsafe { asm!("csrr {}, misa", out(reg) misa) };
+    misa
+}
+
 pub fn get_mcause() -> usize {
     let mcause: usize;
     unsafe { asm!("csrr {}, mcause", out(reg) mcause) };
@@ -0,0 +1,264 @@
+// elf.rs
+// ELF64 loader for RISC-V user programs
+// tongOS team
+
+// Only statically linked executables (ET_EXEC) are supported. Every PT_LOAD
+// segment is copied into freshly allocated pages and mapped into the
+// process page table with the permissions of its program header.
+
+use crate::page::{self, PageTableEntryFlags, Sv39PageTable};
+use crate::tls::TlsTemplate;
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+// e_ident
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+// e_type
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+// e_machine
+const ELF_MACHINE_RISCV: u16 = 243;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+// Largest segment in memory, so a bad header can't take every free page
+const MAX_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
+
+// p_type
+pub const PROGRAM_TYPE_LOAD: u32 = 1;
+pub const PROGRAM_TYPE_TLS: u32 = 7;
+
+// p_flags
+const PROGRAM_FLAG_EXECUTE: u32 = 1 << 0;
+const PROGRAM_FLAG_WRITE: u32 = 1 << 1;
+const PROGRAM_FLAG_READ: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    NotElf64,
+    NotLittleEndian,
+    NotRiscV,
+    NotExecutable,
+    BadProgramHeader,
+    OverlappingSegments,
+    WriteExecuteSegment,
+    OutOfMemory,
+    TlsTooLarge,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: usize,
+    pub virtual_address: usize,
+    pub file_size: usize,
+    pub memory_size: usize,
+    pub align: usize,
+}
+
+impl ProgramHeader {
+    // Translates p_flags into leaf flags for a user page
+    pub fn page_table_flags(&self) -> Result<usize, ElfError> {
+        let mut flags = PageTableEntryFlags::User as usize;
+        if self.flags & PROGRAM_FLAG_READ != 0 {
+            flags |= PageTableEntryFlags::Read as usize;
+        }
+        if self.flags & PROGRAM_FLAG_WRITE != 0 {
+            // Sv39 has no write-only pages
+            flags |= PageTableEntryFlags::ReadWrite as usize;
+        }
+        if self.flags & PROGRAM_FLAG_EXECUTE != 0 {
+            flags |= PageTableEntryFlags::Execute as usize;
+        }
+
+        if page::is_write_execute(flags) {
+            Err(ElfError::WriteExecuteSegment)
+        } else if flags & 0xe == 0 {
+            Err(ElfError::BadProgramHeader)
+        } else {
+            Ok(flags)
+        }
+    }
+}
+
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: usize,
+    program_header_offset: usize,
+    program_header_entry_size: usize,
+    program_header_count: usize,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < ELF_HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[0..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELF_CLASS_64 {
+            return Err(ElfError::NotElf64);
+        }
+        if data[5] != ELF_DATA_LITTLE_ENDIAN {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if read_u16(data, 16) != ELF_TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        if read_u16(data, 18) != ELF_MACHINE_RISCV {
+            return Err(ElfError::NotRiscV);
+        }
+
+        let elf = Elf {
+            data,
+            entry: read_u64(data, 24),
+            program_header_offset: read_u64(data, 32),
+            program_header_entry_size: read_u16(data, 54) as usize,
+            program_header_count: read_u16(data, 56) as usize,
+        };
+
+        // Header fields come from the file, they may overflow
+        let table_end = elf
+            .program_header_entry_size
+            .checked_mul(elf.program_header_count)
+            .and_then(|table_size| elf.program_header_offset.checked_add(table_size));
+        if elf.program_header_entry_size < PROGRAM_HEADER_SIZE
+            || table_end.map_or(true, |table_end| table_end > data.len())
+        {
+            return Err(ElfError::BadProgramHeader);
+        }
+
+        Ok(elf)
+    }
+
+    pub fn program_header(&self, index: usize) -> Result<ProgramHeader, ElfError> {
+        if index >= self.program_header_count {
+            return Err(ElfError::BadProgramHeader);
+        }
+        // Inside the table checked by parse
+        let offset = self.program_header_offset + index * self.program_header_entry_size;
+        let header = ProgramHeader {
+            kind: read_u32(self.data, offset),
+            flags: read_u32(self.data, offset + 4),
+            offset: read_u64(self.data, offset + 8),
+            virtual_address: read_u64(self.data, offset + 16),
+            file_size: read_u64(self.data, offset + 32),
+            memory_size: read_u64(self.data, offset + 40),
+            align: read_u64(self.data, offset + 48),
+        };
+
+        // Segments lie in the file and in the user half of the address
+        // space, so the loader's arithmetic can't overflow
+        let file_end = header.offset.checked_add(header.file_size);
+        let end = header.virtual_address.checked_add(header.memory_size);
+        if header.file_size > header.memory_size
+            || header.memory_size > MAX_SEGMENT_SIZE
+            || file_end.map_or(true, |file_end| file_end > self.data.len())
+            || end.map_or(true, |end| end > page::USER_ADDRESS_LIMIT)
+        {
+            return Err(ElfError::BadProgramHeader);
+        }
+        Ok(header)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = Result<ProgramHeader, ElfError>> + '_ {
+        (0..self.program_header_count).map(move |index| self.program_header(index))
+    }
+
+    /// Template of the thread-local storage (PT_TLS), if the program has
+    /// any thread-local variables.
+    pub fn tls_template(&self) -> Result<Option<TlsTemplate>, ElfError> {
+        for header in self.program_headers() {
+            let header = header?;
+            if header.kind != PROGRAM_TYPE_TLS || header.memory_size == 0 {
+                continue;
+            }
+            let initial = &self.data[header.offset..header.offset + header.file_size];
+            return TlsTemplate::new(
+                initial,
+                header.memory_size,
+                header.align,
+                header.virtual_address,
+            )
+            .map(Some)
+            .ok_or(ElfError::TlsTooLarge);
+        }
+        Ok(None)
+    }
+
+    /// Copies every PT_LOAD segment into new pages and maps them.
+    /// The physical pages are pushed into `pages` so the caller can free
+    /// them, even when loading fails half way.
+    pub fn load(
+        &self,
+        page_table: &mut Sv39PageTable,
+        pages: &mut Vec<*mut u8>,
+    ) -> Result<(), ElfError> {
+        for header in self.program_headers() {
+            let header = header?;
+            if header.kind != PROGRAM_TYPE_LOAD || header.memory_size == 0 {
+                continue;
+            }
+            let flags = header.page_table_flags()?;
+
+            let start = page::align_address_down(header.virtual_address, page::PAGE_ORDER);
+            let end = header.virtual_address + header.memory_size;
+            let file_end = header.virtual_address + header.file_size;
+
+            for page_address in (start..end).step_by(page::PAGE_SIZE) {
+                // Segments must not share pages, they could need different
+                // permissions.
+                if page_table
+                    .virtual_address_translation(page_address)
+                    .is_some()
+                {
+                    return Err(ElfError::OverlappingSegments);
+                }
+
+                let physical_page = page::zalloc(1);
+                if physical_page.is_null() {
+                    return Err(ElfError::OutOfMemory);
+                }
+                pages.push(physical_page);
+
+                // Part of the file that lands in this page, the rest of the
+                // page stays zeroed (.bss)
+                let copy_start = page_address.max(header.virtual_address);
+                let copy_end = (page_address + page::PAGE_SIZE).min(file_end);
+                if copy_start < copy_end {
+                    let file_offset = header.offset + (copy_start - header.virtual_address);
+                    let source = &self.data[file_offset..file_offset + (copy_end - copy_start)];
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            source.as_ptr(),
+                            physical_page.add(copy_start - page_address),
+                            source.len(),
+                        );
+                    }
+                }
+
+                page_table.map(page_address, physical_page as usize, flags, 0);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+// fpu.rs
+// Floating point context of user processes
+// tongOS team
+
+// Processes start with the FPU off (mstatus.FS = Off). The first floating
+// point instruction traps as illegal and gives the process a save area
+// (see enable_on_first_use). From then on its registers are saved on trap
+// entry only when FS says they were written (Dirty), and loaded back when it
+// is switched in, unless they are still in this hart's registers.
+// The kernel itself never uses floating point.
+
+use crate::assembly;
+use crate::cpu;
+
+const MSTATUS_FS_SHIFT: usize = 13;
+const MSTATUS_FS_MASK: usize = 0b11 << MSTATUS_FS_SHIFT;
+
+const NO_HART: usize = core::usize::MAX;
+
+// Pid whose registers are loaded on each hart
+static mut FP_OWNER: [usize; cpu::MAX_HARTS] = [NO_HART; cpu::MAX_HARTS];
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatingPointStatus {
+    Off = 0,
+    Initial = 1,
+    Clean = 2,
+    Dirty = 3,
+}
+
+// Layout used by __tong_os_fp_save/restore: f0-f31 then fcsr
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FloatingPointState {
+    pub fregs: [u64; 32],
+    pub fcsr: u64,
+    // Hart whose registers last held this state
+    loaded_on: usize,
+}
+
+impl FloatingPointState {
+    pub fn new() -> Self {
+        FloatingPointState {
+            fregs: [0; 32],
+            fcsr: 0,
+            loaded_on: NO_HART,
+        }
+    }
+
+    // sigreturn: the registers are loaded again on the next switch
+    pub fn restore(&mut self, fregs: &[u64; 32], fcsr: u64) {
+        self.fregs = *fregs;
+        self.fcsr = fcsr;
+        self.loaded_on = NO_HART;
+    }
+}
+
+pub fn status() -> FloatingPointStatus {
+    match (cpu::get_mstatus() & MSTATUS_FS_MASK) >> MSTATUS_FS_SHIFT {
+        0 => FloatingPointStatus::Off,
+        1 => FloatingPointStatus::Initial,
+        2 => FloatingPointStatus::Clean,
+        _ => FloatingPointStatus::Dirty,
+    }
+}
+
+pub fn set_status(status: FloatingPointStatus) {
+    let mstatus = cpu::get_mstatus() & !MSTATUS_FS_MASK;
+    let mstatus = mstatus | (status as usize) << MSTATUS_FS_SHIFT;
+    unsafe { asm!("csrw mstatus, {}", in(reg) mstatus) }
+}
+
+// Trap entry: keeps the registers of pid if it wrote them since it was
+// switched in
+pub fn save_if_dirty(pid: usize, state: Option<&mut FloatingPointState>) {
+    if status() != FloatingPointStatus::Dirty {
+        return;
+    }
+    if let Some(state) = state {
+        unsafe { assembly::__tong_os_fp_save(state) };
+        state.loaded_on = cpu::get_mhartid();
+        unsafe { FP_OWNER[cpu::get_mhartid()] = pid };
+    }
+    set_status(FloatingPointStatus::Clean);
+}
+
+// Before returning to pid: turns the FPU on only for processes that use it
+pub fn switch_to(pid: usize, state: Option<&mut FloatingPointState>) {
+    let hartid = cpu::get_mhartid();
+    match state {
+        Some(state) => {
+            set_status(FloatingPointStatus::Clean);
+            let owner = unsafe { FP_OWNER[hartid] };
+            if owner != pid || state.loaded_on != hartid {
+                unsafe {
+                    assembly::__tong_os_fp_restore(state);
+                    FP_OWNER[hartid] = pid;
+                }
+                state.loaded_on = hartid;
+                set_status(FloatingPointStatus::Clean);
+            }
+        }
+        None => set_status(FloatingPointStatus::Off),
+    }
+}
+
+// Illegal instruction trap: if the FPU was off and the process never used
+// it, this is (most likely) its first floating point instruction. Gives it
+// a zeroed state, the instruction runs again with the FPU on and, if it was
+// illegal after all, traps again as a real SIGILL.
+pub fn enable_on_first_use(state: &mut Option<FloatingPointState>) -> bool {
+    if state.is_some() || status() != FloatingPointStatus::Off {
+        return false;
+    }
+    *state = Some(FloatingPointState::new());
+    true
+}
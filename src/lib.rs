@@ -6,6 +6,7 @@
 #![feature(llvm_asm)]
 #![feature(alloc_error_handler)]
 #![feature(custom_test_frameworks)]
+#![feature(thread_local)]
 #![test_runner(crate::test_runner)]
 
 extern crate alloc;
@@ -14,8 +15,18 @@ extern crate alloc;
 // 2 = Philosopher's Dinner;
 // 3 = Keyboard input app example.
 // 4 = All processess.
+// 5 = Hello world ELF program embedded in the kernel.
+// 6 = Signals example.
+// 7 = Floating point threads.
+// 8 = Vector (RVV) threads, needs `-cpu rv64,v=true`.
+// 9 = Rust ELF program using the user runtime (user/).
+// 10 = ELF program spawning others with arguments and environment.
+// 11 = Periodic real-time tasks (EDF) with admission control.
 pub const PROCESS_TO_RUN: usize = 2;
 
+// Scheduling policy at boot, one of abi::SCHED_*
+pub const SCHEDULER: usize = abi::SCHED_ROUND_ROBIN;
+
 pub static mut DEBUG_OUTPUT: bool = false;
 pub const ENABLE_PREEMPTION: bool = true;
 
@@ -80,15 +91,25 @@ macro_rules! debug {
     }};
 }
 
+pub mod abi;
+pub mod address_space;
 pub mod app;
 pub mod assembly;
 pub mod assignment;
+pub mod console;
 pub mod cpu;
+pub mod elf;
+pub mod fpu;
 pub mod kmem;
 pub mod lock;
 pub mod page;
 pub mod plic;
 pub mod process;
 pub mod scheduler;
+pub mod signal;
+pub mod syscall;
+pub mod tls;
 pub mod trap;
 pub mod uart;
+pub mod user_memory;
+pub mod vector;
@@ -82,6 +82,7 @@ extern "C" fn kinit(hartid: usize) -> ! {
 
         println!("setup trap");
         tong_os::trap::init();
+        tong_os::console::init();
 
         println!("Init process");
         tong_os::process::init();
@@ -108,6 +109,7 @@ extern "C" fn kinit(hartid: usize) -> ! {
         }
 
         tong_os::assignment::choose_processes(tong_os::PROCESS_TO_RUN);
+        tong_os::process::audit_page_tables();
 
         tong_os::scheduler::schedule();
     } else {
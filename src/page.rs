@@ -3,13 +3,18 @@
 //  Stephen Marz
 //  tongOS team
 
-use crate::assembly::{HEAP_SIZE, HEAP_START};
+use crate::assembly::{
+    BSS_END, DATA_END, DATA_START, HEAP_SIZE, HEAP_START, RODATA_END, RODATA_START,
+};
 use crate::lock::Mutex;
 
 // Page size = 4096 bytes
 pub const PAGE_ORDER: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_ORDER;
 
+// User addresses live in the lower half of the Sv39 address space
+pub const USER_ADDRESS_LIMIT: usize = 1 << 38;
+
 pub static mut NUMBER_OF_PAGES: usize = 0;
 pub static mut PAGE_TABLE_START_ADDRESS: usize = 0;
 pub static mut PAGE_DESCRIPTOR_PTR: *mut PageDescriptor = core::ptr::null_mut();
@@ -30,6 +35,12 @@ pub const fn align_address(address: usize, order: usize) -> usize {
     (address + mask) & !mask
 }
 
+/// Same as align_address, but rounds down.
+pub const fn align_address_down(address: usize, order: usize) -> usize {
+    let mask = (1usize << order) - 1;
+    address & !mask
+}
+
 #[repr(u8)]
 pub enum PageDescriptorFlags {
     Taken = 1 << 0,
@@ -82,6 +93,7 @@ pub enum PageTableEntryFlags {
     ReadWriteExecute = 1 << 1 | 1 << 2 | 1 << 3,
 
     // User Convenience Combinations
+    UserRead = 1 << 1 | 1 << 4,
     UserReadWrite = 1 << 1 | 1 << 2 | 1 << 4,
     UserReadExecute = 1 << 1 | 1 << 3 | 1 << 4,
     UserReadWriteExecute = 1 << 1 | 1 << 2 | 1 << 3 | 1 << 4,
@@ -109,6 +121,10 @@ impl Sv39PageTableEntry {
         self.entry & PageTableEntryFlags::Execute as usize == PageTableEntryFlags::Execute as usize
     }
 
+    pub fn is_user(&self) -> bool {
+        self.entry & PageTableEntryFlags::User as usize == PageTableEntryFlags::User as usize
+    }
+
     pub fn get_physical_address(&self) -> usize {
         (self.entry & !0x3ff) << 2
     }
@@ -118,6 +134,12 @@ impl Sv39PageTableEntry {
     }
 }
 
+/// W^X: a page may be writable or executable, never both.
+pub fn is_write_execute(flags: usize) -> bool {
+    let write_execute = PageTableEntryFlags::Write as usize | PageTableEntryFlags::Execute as usize;
+    flags & write_execute == write_execute
+}
+
 // 2^9 = 512 entries per table
 #[repr(C)]
 pub struct Sv39PageTable {
@@ -134,13 +156,43 @@ impl Sv39PageTable {
     }
 
     // Map a virtual address to a physical address using 4096-byte page
-    // size.
+    // size. Writable and executable mappings are rejected, see
+    // map_write_execute for the explicit override.
     pub fn map(
         &mut self,
         virtual_address: usize,
         physical_address: usize,
         flags: usize,
         level: usize,
+    ) {
+        assert!(
+            !is_write_execute(flags),
+            "W^X violation: mapping {:#x} -> {:#x} with flags {:#x}",
+            virtual_address,
+            physical_address,
+            flags
+        );
+        self.map_unchecked(virtual_address, physical_address, flags, level);
+    }
+
+    // Same as map, but allows a page to be both writable and executable.
+    // Every caller of this is a W^X hole, so it should have a good reason.
+    pub fn map_write_execute(
+        &mut self,
+        virtual_address: usize,
+        physical_address: usize,
+        flags: usize,
+        level: usize,
+    ) {
+        self.map_unchecked(virtual_address, physical_address, flags, level);
+    }
+
+    fn map_unchecked(
+        &mut self,
+        virtual_address: usize,
+        physical_address: usize,
+        flags: usize,
+        level: usize,
     ) {
         // Make sure that Read, Write, or Execute have been provided
         // otherwise, we'll leak memory and always create a page fault.
@@ -201,7 +253,66 @@ impl Sv39PageTable {
         }
     }
 
+    /// Removes the leaf entry mapping virtual_address, if there is one.
+    /// The physical page is not freed.
+    pub fn unmap_page(&mut self, virtual_address: usize) {
+        let virtual_page_number = [
+            (virtual_address >> 12) & 0x1ff,
+            (virtual_address >> 21) & 0x1ff,
+            (virtual_address >> 30) & 0x1ff,
+        ];
+
+        let mut page_table_entry = &mut self.entries[virtual_page_number[2]];
+
+        for i in (0..(Sv39PageTable::levels() - 1)).rev() {
+            if !page_table_entry.is_valid()
+                || page_table_entry.is_leaf()
+                || page_table_entry.is_executable()
+            {
+                break;
+            }
+            let entry_as_table = page_table_entry.get_physical_address() as *mut Sv39PageTable;
+            page_table_entry = unsafe { &mut (*entry_as_table).entries[virtual_page_number[i]] };
+        }
+
+        if page_table_entry.is_leaf() || page_table_entry.is_executable() {
+            page_table_entry.entry = 0;
+        }
+    }
+
+    /// Calls f for every valid leaf entry with the virtual address it maps.
+    pub fn for_each_leaf<F: FnMut(usize, &Sv39PageTableEntry)>(&self, f: &mut F) {
+        self.for_each_leaf_at_level(Sv39PageTable::levels() - 1, 0, f);
+    }
+
+    fn for_each_leaf_at_level<F: FnMut(usize, &Sv39PageTableEntry)>(
+        &self,
+        level: usize,
+        virtual_address: usize,
+        f: &mut F,
+    ) {
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !entry.is_valid() {
+                continue;
+            }
+            let virtual_address = virtual_address | index << (12 + level * 9);
+            if entry.is_leaf() || entry.is_executable() {
+                f(virtual_address, entry);
+            } else if level > 0 {
+                let table = entry.get_physical_address() as *const Sv39PageTable;
+                unsafe { (*table).for_each_leaf_at_level(level - 1, virtual_address, f) };
+            }
+        }
+    }
+
     pub fn virtual_address_translation(&self, virtual_address: usize) -> Option<usize> {
+        self.leaf_translation(virtual_address)
+            .map(|(physical_address, _)| physical_address)
+    }
+
+    // Physical address and leaf entry of virtual_address, to also check
+    // the permissions of the mapping
+    pub fn leaf_translation(&self, virtual_address: usize) -> Option<(usize, &Sv39PageTableEntry)> {
         // Sv39 virtual address (9 bits each)
         let virtual_page_number = [
             (virtual_address >> 12) & 0x1ff,
@@ -235,7 +346,7 @@ impl Sv39PageTable {
                 // pa.ppn[]
                 let addr = ((page_table_entry.entry << 2) as usize) & !offset_mask;
 
-                return Some(addr | vaddr_pgoff);
+                return Some((addr | vaddr_pgoff, page_table_entry));
             }
 
             let entry_as_table = page_table_entry.get_physical_address() as *const Sv39PageTable;
@@ -247,6 +358,42 @@ impl Sv39PageTable {
     }
 }
 
+/// Walks every leaf of a page table checking W^X and the kernel section
+/// permissions: nothing may be writable and executable, .rodata must be
+/// read-only and .data/.bss must never be executable.
+/// Prints each violation and returns how many were found.
+pub fn audit_page_table(page_table: &Sv39PageTable) -> usize {
+    let (rodata, data) = unsafe {
+        (
+            align_address_down(RODATA_START, PAGE_ORDER)..RODATA_END,
+            align_address_down(DATA_START, PAGE_ORDER)..BSS_END.max(DATA_END),
+        )
+    };
+    let mut violations = 0;
+
+    page_table.for_each_leaf(&mut |virtual_address, entry| {
+        let problem = if entry.is_writable() && entry.is_executable() {
+            "writable and executable"
+        } else if rodata.contains(&virtual_address) && entry.is_writable() {
+            ".rodata is writable"
+        } else if data.contains(&virtual_address) && entry.is_executable() {
+            ".data/.bss is executable"
+        } else {
+            return;
+        };
+        violations += 1;
+        println!(
+            "W^X audit: page {:#x} -> {:#x} (flags {:#x}): {}",
+            virtual_address,
+            entry.get_physical_address(),
+            entry.entry & 0xff,
+            problem
+        );
+    });
+
+    violations
+}
+
 // Alloc 1 page strucutre per 4k bytes
 pub fn init() {
     unsafe {
@@ -3,21 +3,34 @@
 // Stephen Marz
 // tongOS team
 
+use crate::abi::{self, Errno, ProcessInfo, ResourceUsage, SyscallResult};
+use crate::address_space::AddressSpace;
 use crate::assembly;
+use crate::console;
 use crate::cpu::{self, CpuMode, TrapFrame};
+use crate::elf;
+use crate::fpu::{self, FloatingPointState};
 use crate::lock::Mutex;
 use crate::page::{self, PageTableEntryFlags, Sv39PageTable};
-use crate::scheduler;
+use crate::scheduler::{self, CfsState, MlfqState, ReadyQueue, RealTime};
+use crate::signal::{self, SignalState};
 use crate::trap;
+use crate::vector::{self, VectorState};
 
 use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 pub const IDLE_ID: usize = core::usize::MAX;
 
 static mut PROCESS_RUNNING: [Option<Process>; 4] = [None, None, None, None];
+// Held by a hart while it takes or puts back its running process, and by
+// other harts while they read it. Only the allocator may lock under it.
+static mut PROCESS_RUNNING_LOCK: [Mutex; cpu::MAX_HARTS] = [Mutex::new(); cpu::MAX_HARTS];
 static mut PROCESS_IDLE: [Option<Process>; 4] = [None, None, None, None];
 
-static mut PROCESS_READY: [Option<VecDeque<Process>>; 4] = [None, None, None, None];
+static mut PROCESS_READY: [Option<ReadyQueue>; 4] = [None, None, None, None];
 static mut PROCESS_READY_LOCK: [Mutex; 4] = [Mutex::new(); 4];
 
 static mut PROCESS_SLEEPING: Option<VecDeque<Process>> = None;
@@ -26,23 +39,89 @@ static mut PROCESS_SLEEPING_LOCK: Mutex = Mutex::new();
 static mut PROCESS_BLOCKED: Option<VecDeque<Process>> = None;
 static mut PROCESS_BLOCKED_LOCK: Mutex = Mutex::new();
 
-static mut PID_LIST: Option<VecDeque<(usize, Option<usize>)>> = None;
+// Suspended by SIGSTOP until SIGCONT (or SIGKILL)
+static mut PROCESS_STOPPED: Option<VecDeque<Process>> = None;
+static mut PROCESS_STOPPED_LOCK: Mutex = Mutex::new();
+
+// Every pid that has not been reaped yet, forming the process tree. An
+// exited process keeps its entry and waits in PROCESS_ZOMBIE, with its exit
+// code, until someone joins it.
+// PID_LIST_LOCK also guards PROCESS_ZOMBIE.
+static mut PID_LIST: Option<VecDeque<PidEntry>> = None;
 static mut PID_LIST_LOCK: Mutex = Mutex::new();
 
+static mut PROCESS_ZOMBIE: Option<VecDeque<Process>> = None;
+
+// Init is not a real process: it is the kernel itself, parent of the
+// processes started at boot and adopter of orphans. It never joins, so its
+// children are reaped as soon as they exit.
+pub const INIT_PID: usize = 0;
+
+struct PidEntry {
+    pid: usize,
+    parent: usize,
+    children: Vec<usize>,
+    // Processes blocked joining this one
+    waiters: Vec<usize>,
+    // Signals raised but not delivered yet, one bit per signal
+    pending_signals: u64,
+    // Set by another hart while the process ran, see update_process
+    attributes: Option<Attributes>,
+}
+
+impl PidEntry {
+    fn new(pid: usize, parent: usize) -> Self {
+        PidEntry {
+            pid,
+            parent,
+            children: Vec::new(),
+            waiters: Vec::new(),
+            pending_signals: 0,
+            attributes: None,
+        }
+    }
+}
+
 pub fn running_process() -> &'static Process {
     unsafe { PROCESS_RUNNING[cpu::get_mhartid()].as_ref().unwrap() }
 }
 
-fn running_process_mut() -> &'static mut Process {
+pub fn running_process_mut() -> &'static mut Process {
     unsafe { PROCESS_RUNNING[cpu::get_mhartid()].as_mut().unwrap() }
 }
 
 fn running_process_take() -> Process {
-    unsafe { PROCESS_RUNNING[cpu::get_mhartid()].take().unwrap() }
+    let hartid = cpu::get_mhartid();
+    get_running_lock(hartid).spin_lock();
+    let mut running = unsafe { PROCESS_RUNNING[hartid].take().unwrap() };
+    get_running_lock(hartid).unlock();
+    running.account_kernel_time();
+    running
 }
 
 pub fn running_process_replace(running: Process) {
-    unsafe { PROCESS_RUNNING[cpu::get_mhartid()].replace(running) };
+    let hartid = cpu::get_mhartid();
+    get_running_lock(hartid).spin_lock();
+    unsafe { PROCESS_RUNNING[hartid].replace(running) };
+    get_running_lock(hartid).unlock();
+}
+
+fn get_running_lock(hartid: usize) -> &'static mut Mutex {
+    unsafe { &mut PROCESS_RUNNING_LOCK[hartid] }
+}
+
+// Calls f with the process running on hartid, None while the hart is
+// switching. f may allocate but must not take other locks.
+fn with_running_process<R, F: FnOnce(Option<&Process>) -> R>(hartid: usize, f: F) -> R {
+    get_running_lock(hartid).spin_lock();
+    let result = f(running_list()[hartid].as_ref());
+    get_running_lock(hartid).unlock();
+    result
+}
+
+// pid of the process running on hartid, for other harts
+pub fn running_pid(hartid: usize) -> Option<usize> {
+    with_running_process(hartid, |running| running.map(|process| process.pid))
 }
 
 pub fn idle_process_take() -> Process {
@@ -57,23 +136,19 @@ pub fn running_list() -> &'static [Option<Process>] {
     unsafe { PROCESS_RUNNING.as_ref() }
 }
 
-fn running_list_mut() -> &'static mut [Option<Process>] {
-    unsafe { PROCESS_RUNNING.as_mut() }
-}
-
-fn ready_list() -> &'static VecDeque<Process> {
+fn ready_list() -> &'static ReadyQueue {
     unsafe { PROCESS_READY[cpu::get_mhartid()].as_ref().unwrap() }
 }
 
-pub fn ready_list_mut() -> &'static mut VecDeque<Process> {
+pub fn ready_list_mut() -> &'static mut ReadyQueue {
     unsafe { PROCESS_READY[cpu::get_mhartid()].as_mut().unwrap() }
 }
 
-fn ready_list_by_hartid(hartid: usize) -> &'static VecDeque<Process> {
+fn ready_list_by_hartid(hartid: usize) -> &'static ReadyQueue {
     unsafe { PROCESS_READY[hartid].as_ref().unwrap() }
 }
 
-pub fn ready_list_by_hartid_mut(hartid: usize) -> &'static mut VecDeque<Process> {
+pub fn ready_list_by_hartid_mut(hartid: usize) -> &'static mut ReadyQueue {
     unsafe { PROCESS_READY[hartid].as_mut().unwrap() }
 }
 
@@ -89,6 +164,18 @@ fn get_blocked_list_lock() -> &'static mut Mutex {
     unsafe { &mut PROCESS_BLOCKED_LOCK }
 }
 
+fn stopped_list() -> &'static VecDeque<Process> {
+    unsafe { PROCESS_STOPPED.as_ref().unwrap() }
+}
+
+fn stopped_list_mut() -> &'static mut VecDeque<Process> {
+    unsafe { PROCESS_STOPPED.as_mut().unwrap() }
+}
+
+fn get_stopped_list_lock() -> &'static mut Mutex {
+    unsafe { &mut PROCESS_STOPPED_LOCK }
+}
+
 fn sleeping_list() -> &'static VecDeque<Process> {
     unsafe { PROCESS_SLEEPING.as_ref().unwrap() }
 }
@@ -109,12 +196,20 @@ pub fn get_ready_list_lock_by_hartid(hartid: usize) -> &'static mut Mutex {
     unsafe { &mut PROCESS_READY_LOCK[hartid] }
 }
 
-fn pid_list() -> &'static VecDeque<(usize, Option<usize>)> {
-    unsafe { PID_LIST.as_ref().unwrap() }
+fn pid_list_mut() -> &'static mut VecDeque<PidEntry> {
+    unsafe { PID_LIST.as_mut().unwrap() }
 }
 
-fn pid_list_mut() -> &'static mut VecDeque<(usize, Option<usize>)> {
-    unsafe { PID_LIST.as_mut().unwrap() }
+fn pid_entry_mut(pid: usize) -> Option<&'static mut PidEntry> {
+    pid_list_mut().iter_mut().find(|entry| entry.pid == pid)
+}
+
+fn zombie_list() -> &'static VecDeque<Process> {
+    unsafe { PROCESS_ZOMBIE.as_ref().unwrap() }
+}
+
+fn zombie_list_mut() -> &'static mut VecDeque<Process> {
+    unsafe { PROCESS_ZOMBIE.as_mut().unwrap() }
 }
 
 pub fn get_pid_list_lock() -> &'static mut Mutex {
@@ -122,6 +217,18 @@ pub fn get_pid_list_lock() -> &'static mut Mutex {
 }
 
 static DEFAULT_QUANTUM: usize = 1;
+const NICE_PER_QUANTUM: usize = 5;
+
+// Quanta grow by one every NICE_PER_QUANTUM below the largest nice, which
+// gets DEFAULT_QUANTUM: 4 for the default nice and 8 for the smallest
+fn nice_quantum(nice: isize) -> usize {
+    DEFAULT_QUANTUM * (1 + (abi::NICE_MAX - nice) as usize / NICE_PER_QUANTUM)
+}
+
+const USER_STACK_PAGES: usize = 12;
+const USER_STACK_SIZE: usize = USER_STACK_PAGES * page::PAGE_SIZE;
+
+const IDLE_STACK_PAGES: usize = 2;
 
 static mut NEXT_PID: usize = 0;
 static mut NEXT_PID_LOCK: Mutex = Mutex::new();
@@ -141,7 +248,7 @@ pub fn get_next_pid() -> usize {
 pub fn init() {
     unsafe {
         for list in PROCESS_READY.as_mut().iter_mut() {
-            list.replace(VecDeque::new());
+            list.replace(ReadyQueue::new());
         }
     }
     unsafe {
@@ -150,9 +257,16 @@ pub fn init() {
     unsafe {
         PROCESS_BLOCKED.replace(VecDeque::new());
     }
+    unsafe {
+        PROCESS_STOPPED.replace(VecDeque::new());
+    }
     unsafe {
         PID_LIST.replace(VecDeque::new());
     }
+    pid_list_mut().push_back(PidEntry::new(INIT_PID, INIT_PID));
+    unsafe {
+        PROCESS_ZOMBIE.replace(VecDeque::new());
+    }
     for process in unsafe { &mut PROCESS_IDLE } {
         process.replace(Process::new_idle());
     }
@@ -166,6 +280,11 @@ pub fn init() {
 // Running -> Sleeping = process sleep
 // Blocked -> Ready = input available now
 // Sleeping -> Running/Ready = wake up
+// Blocked/Sleeping -> Ready = interrupted by a signal
+// Running -> Stopped = SIGSTOP
+// Blocked/Sleeping -> Stopped = woken or interrupted after a SIGSTOP
+// Stopped -> Ready = SIGCONT or SIGKILL
+// Running -> Zombie = exited, keeps the exit code until joined
 #[repr(C)]
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum ProcessState {
@@ -173,6 +292,8 @@ pub enum ProcessState {
     Running(usize),
     Blocked,
     Sleeping(usize),
+    Stopped,
+    Zombie(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -181,111 +302,191 @@ pub struct Process {
     pub trap_frame: *mut TrapFrame,
     pub stack: *mut u8,
     pub state: ProcessState,
-    pub page_table: *mut Sv39PageTable,
+    // Shared by every thread of a program, None for idle and zombies
+    pub address_space: Option<Arc<AddressSpace>>,
     pub quantum: usize,
+    pub nice: isize,
+    // Harts it may run on, see abi::AFFINITY_ALL
+    pub affinity: u64,
+    pub mlfq: MlfqState,
+    pub cfs: CfsState,
+    // Set for periodic real-time tasks
+    pub realtime: Option<RealTime>,
     pub pid: usize,
-    pub blocking_pid: Option<usize>,
     pub sleep_until: usize,
     pub previous_hart: usize,
+    pub signals: SignalState,
+    // SIGSTOP came while it was blocked or sleeping: it keeps waiting, and
+    // goes to the stopped list instead of a ready one when woken
+    pub stopped: bool,
+    // None until the process runs its first floating point instruction
+    pub fp_state: Option<FloatingPointState>,
+    // Same for the vector registers
+    pub vector_state: Option<VectorState>,
+    pub name: String,
+    // mtime when it was created
+    pub created_at: usize,
+    pub usage: ResourceUsage,
+    // mtime up to which usage is accounted for
+    accounted_at: usize,
+    // Hart it last ran on, to count migrations
+    ran_on: Option<usize>,
 }
 
 impl Process {
-    pub fn new(start: usize, arg0: usize, arg1: usize, arg2: usize) -> Self {
+    // New program linked into the kernel, running in its own address space
+    pub fn new(name: &str, start: usize, arg0: usize, arg1: usize, arg2: usize) -> Self {
         let pid = get_next_pid();
+        let address_space = AddressSpace::new_kernel_image(pid);
+        Process::new_in_address_space(
+            name,
+            address_space,
+            SignalState::new(),
+            pid,
+            start,
+            [arg0, arg1, arg2],
+        )
+    }
 
-        let page_table_address = page::zalloc(1);
-        assert!(page_table_address as *const u8 != core::ptr::null());
+    // New thread sharing the address space of parent. Only the stack and
+    // the trap frame are its own. Name, priority, signal handlers and mask
+    // are inherited.
+    pub fn new_thread(
+        parent: &Process,
+        start: usize,
+        arg0: usize,
+        arg1: usize,
+        arg2: usize,
+    ) -> Self {
+        let address_space = parent.address_space.as_ref().unwrap().clone();
+        let mut thread = Process::new_in_address_space(
+            &parent.name,
+            address_space,
+            parent.signals.clone(),
+            get_next_pid(),
+            start,
+            [arg0, arg1, arg2],
+        );
+        thread.set_nice(parent.nice);
+        thread.affinity = parent.affinity;
+        thread
+    }
 
+    fn new_in_address_space(
+        name: &str,
+        address_space: Arc<AddressSpace>,
+        signals: SignalState,
+        pid: usize,
+        start: usize,
+        args: [usize; 3],
+    ) -> Self {
         let mut context = TrapFrame::new();
-        context.regs[cpu::GeneralPurposeRegister::A0 as usize] = arg0;
-        context.regs[cpu::GeneralPurposeRegister::A1 as usize] = arg1;
-        context.regs[cpu::GeneralPurposeRegister::A2 as usize] = arg2;
-        context.satp = cpu::build_satp(pid, page_table_address as usize);
+        context.regs[cpu::GeneralPurposeRegister::A0 as usize] = args[0];
+        context.regs[cpu::GeneralPurposeRegister::A1 as usize] = args[1];
+        context.regs[cpu::GeneralPurposeRegister::A2 as usize] = args[2];
+        context.satp = address_space.satp();
         context.pc = start as usize;
         context.global_interrupt_enable = 0;
         context.mode = CpuMode::User as usize;
 
-        let num_stack_pages = 12;
-        let stack = page::zalloc(num_stack_pages) as usize;
-        assert!(stack as *const u8 != core::ptr::null());
-        let stack_end = stack + num_stack_pages * page::PAGE_SIZE;
+        let stack = alloc_user_stack(&address_space);
+        let (thread_pointer, stack_top) = push_tls(&address_space, stack + USER_STACK_SIZE);
+        context.regs[cpu::GeneralPurposeRegister::Tp as usize] = thread_pointer;
+        let trap_frame = push_trap_frame(&mut context, stack_top);
 
-        context.regs[cpu::GeneralPurposeRegister::Sp as usize] =
-            stack_end - core::mem::size_of::<TrapFrame>();
+        Process {
+            trap_frame,
+            stack: stack as *mut u8,
+            state: ProcessState::Ready,
+            address_space: Some(address_space),
+            quantum: nice_quantum(abi::NICE_DEFAULT),
+            nice: abi::NICE_DEFAULT,
+            affinity: abi::AFFINITY_ALL,
+            mlfq: MlfqState::default(),
+            cfs: CfsState::default(),
+            realtime: None,
+            pid,
+            sleep_until: 0,
+            previous_hart: cpu::get_mhartid(),
+            signals,
+            stopped: false,
+            fp_state: None,
+            vector_state: None,
+            name: String::from(name),
+            created_at: trap::get_mtime() as usize,
+            usage: ResourceUsage::new(),
+            accounted_at: 0,
+            ran_on: None,
+        }
+    }
 
-        let trap_frame = context.regs[cpu::GeneralPurposeRegister::Sp as usize] as *mut TrapFrame;
+    // Loads a statically linked ELF executable into a fresh address space.
+    // The program starts at e_entry with the initial stack described in
+    // abi.rs: argc, argv, envp and the auxiliary vector.
+    pub fn new_from_elf(
+        name: &str,
+        image: &[u8],
+        argv: &[&str],
+        envp: &[&str],
+    ) -> Result<Self, elf::ElfError> {
+        let elf = elf::Elf::parse(image)?;
+        let pid = get_next_pid();
 
-        unsafe {
-            let source = &context as *const TrapFrame;
-            core::ptr::copy(source, trap_frame, 1);
-        }
+        // On error the address space is dropped with whatever was loaded
+        let mut address_space = AddressSpace::new(pid);
+        let mut image_pages = Vec::new();
+        let loaded = elf.load(address_space.page_table_mut(), &mut image_pages);
+        address_space.image_pages = image_pages;
+        loaded?;
+        address_space.tls = elf.tls_template()?;
+
+        let address_space = Arc::new(address_space);
+        let stack = alloc_user_stack(&address_space);
+        let (thread_pointer, stack_end) = push_tls(&address_space, stack + USER_STACK_SIZE);
+        let auxiliary_vector = [
+            (abi::AT_PAGESZ, page::PAGE_SIZE),
+            (abi::AT_ENTRY, elf.entry),
+            (abi::AT_HWCAP, cpu::get_misa() & ((1 << 26) - 1)),
+        ];
+        let (stack_top, argv_address, envp_address) =
+            push_initial_stack(stack_end, argv, envp, &auxiliary_vector);
 
-        let page_table = page_table_address as *mut Sv39PageTable;
+        let mut context = TrapFrame::new();
+        context.regs[cpu::GeneralPurposeRegister::A0 as usize] = argv.len();
+        context.regs[cpu::GeneralPurposeRegister::A1 as usize] = argv_address;
+        context.regs[cpu::GeneralPurposeRegister::A2 as usize] = envp_address;
+        context.regs[cpu::GeneralPurposeRegister::Tp as usize] = thread_pointer;
+        context.satp = address_space.satp();
+        context.pc = elf.entry;
+        context.global_interrupt_enable = 0;
+        context.mode = CpuMode::User as usize;
 
-        unsafe {
-            for address in (stack..stack_end).step_by(page::PAGE_SIZE) {
-                (*page_table).map(
-                    address,
-                    address,
-                    PageTableEntryFlags::UserReadWrite as usize,
-                    0,
-                );
-            }
-            for address in (assembly::RODATA_START..assembly::RODATA_END).step_by(page::PAGE_SIZE) {
-                (*page_table).map(
-                    address as usize,
-                    address as usize,
-                    PageTableEntryFlags::UserReadWrite as usize,
-                    0,
-                );
-            }
-            for address in (assembly::DATA_START..assembly::DATA_END).step_by(page::PAGE_SIZE) {
-                (*page_table).map(
-                    address as usize,
-                    address as usize,
-                    PageTableEntryFlags::UserReadWrite as usize,
-                    0,
-                );
-            }
-            for address in (assembly::BSS_START..assembly::BSS_END).step_by(page::PAGE_SIZE) {
-                (*page_table).map(
-                    address as usize,
-                    address as usize,
-                    PageTableEntryFlags::UserReadWrite as usize,
-                    0,
-                );
-            }
-            for address in (assembly::HEAP_START..assembly::HEAP_START + assembly::HEAP_SIZE)
-                .step_by(page::PAGE_SIZE)
-            {
-                (*page_table).map(
-                    address as usize,
-                    address as usize,
-                    PageTableEntryFlags::UserReadWrite as usize,
-                    0,
-                );
-            }
-            for address in (assembly::TEXT_START..assembly::TEXT_END).step_by(page::PAGE_SIZE) {
-                (*page_table).map(
-                    address as usize,
-                    address as usize,
-                    PageTableEntryFlags::UserReadExecute as usize,
-                    0,
-                );
-            }
-        }
+        let trap_frame = push_trap_frame(&mut context, stack_top);
 
-        Process {
+        Ok(Process {
             trap_frame,
             stack: stack as *mut u8,
             state: ProcessState::Ready,
-            page_table,
-            quantum: DEFAULT_QUANTUM,
+            address_space: Some(address_space),
+            quantum: nice_quantum(abi::NICE_DEFAULT),
+            nice: abi::NICE_DEFAULT,
+            affinity: abi::AFFINITY_ALL,
+            mlfq: MlfqState::default(),
+            cfs: CfsState::default(),
+            realtime: None,
             pid,
-            blocking_pid: None,
             sleep_until: 0,
             previous_hart: cpu::get_mhartid(),
-        }
+            signals: SignalState::new(),
+            stopped: false,
+            fp_state: None,
+            vector_state: None,
+            name: String::from(name),
+            created_at: trap::get_mtime() as usize,
+            usage: ResourceUsage::new(),
+            accounted_at: 0,
+            ran_on: None,
+        })
     }
 
     pub fn new_idle() -> Self {
@@ -294,109 +495,428 @@ impl Process {
         context.global_interrupt_enable = 1;
         context.mode = CpuMode::Machine as usize;
 
-        let num_stack_pages = 2;
-        let stack = page::zalloc(num_stack_pages) as usize;
+        let stack = page::zalloc(IDLE_STACK_PAGES) as usize;
         assert!(stack as *const u8 != core::ptr::null());
-        let stack_end = stack + num_stack_pages * page::PAGE_SIZE;
+        let stack_end = stack + IDLE_STACK_PAGES * page::PAGE_SIZE;
 
-        context.regs[cpu::GeneralPurposeRegister::Sp as usize] =
-            stack_end - core::mem::size_of::<TrapFrame>();
-
-        let trap_frame = context.regs[cpu::GeneralPurposeRegister::Sp as usize] as *mut TrapFrame;
-
-        unsafe {
-            let source = &context as *const TrapFrame;
-            core::ptr::copy(source, trap_frame, 1);
-        }
+        let trap_frame = push_trap_frame(&mut context, stack_end);
 
         Process {
             trap_frame,
             stack: stack as *mut u8,
             state: ProcessState::Ready,
-            page_table: 0 as *mut _,
+            address_space: None,
             quantum: DEFAULT_QUANTUM,
+            nice: abi::NICE_DEFAULT,
+            affinity: abi::AFFINITY_ALL,
+            mlfq: MlfqState::default(),
+            cfs: CfsState::default(),
+            realtime: None,
             pid: IDLE_ID,
-            blocking_pid: None,
             sleep_until: 0,
             previous_hart: cpu::get_mhartid(),
+            signals: SignalState::new(),
+            stopped: false,
+            fp_state: None,
+            vector_state: None,
+            name: String::from("idle"),
+            created_at: trap::get_mtime() as usize,
+            usage: ResourceUsage::new(),
+            accounted_at: 0,
+            ran_on: None,
         }
     }
 
     pub fn get_trap_frame(&self) -> *mut TrapFrame {
         self.trap_frame
     }
+
+    pub fn page_table(&self) -> Option<&Sv39PageTable> {
+        self.address_space
+            .as_ref()
+            .map(|address_space| address_space.page_table())
+    }
+
+    // Stack, plus the loaded program for ELF processes (shared by its
+    // threads). Programs linked into the kernel share the kernel image.
+    pub fn memory(&self) -> usize {
+        let stack = if self.stack.is_null() {
+            0
+        } else if self.pid == IDLE_ID {
+            IDLE_STACK_PAGES * page::PAGE_SIZE
+        } else {
+            USER_STACK_SIZE
+        };
+        let image = self.address_space.as_ref().map_or(0, |address_space| {
+            address_space.image_pages.len() * page::PAGE_SIZE
+        });
+        stack + image
+    }
+
+    pub fn set_nice(&mut self, nice: isize) {
+        self.nice = nice;
+        self.quantum = nice_quantum(nice);
+    }
+
+    // CPU time accounting. A process is charged when it gets a hart, at
+    // trap entry (the time since then was spent in user mode, or in the
+    // kernel for idle) and at trap exit or when it leaves the hart (the
+    // time since then was spent in the kernel).
+    // Returns true if it last ran on another hart.
+    pub fn account_switch_to(&mut self, hartid: usize) -> bool {
+        self.usage.context_switches += 1;
+        let migrated = self.ran_on.map_or(false, |ran_on| ran_on != hartid);
+        if migrated {
+            self.usage.migrations += 1;
+        }
+        self.ran_on = Some(hartid);
+        self.accounted_at = trap::get_mtime() as usize;
+        migrated
+    }
+
+    // None if it has not run yet
+    pub fn last_hart(&self) -> Option<usize> {
+        self.ran_on
+    }
+
+    // Hart that admitted it, for a real-time task
+    fn realtime_hart(&self) -> Option<usize> {
+        self.realtime.as_ref().map(|realtime| realtime.hart)
+    }
+
+    // Only for the process running on this hart
+    fn account_user_time(&mut self) {
+        self.usage.user_time += self.take_unaccounted_time();
+        self.record_idle_time();
+    }
+
+    fn account_kernel_time(&mut self) {
+        self.usage.kernel_time += self.take_unaccounted_time();
+        self.record_idle_time();
+    }
+
+    // Other harts read the idle time from the scheduler statistics, never
+    // from the idle process itself
+    fn record_idle_time(&self) {
+        if self.pid == IDLE_ID {
+            scheduler::record_idle_time(self.usage.cpu_time());
+        }
+    }
+
+    fn take_unaccounted_time(&mut self) -> usize {
+        let now = trap::get_mtime() as usize;
+        let elapsed = now.wrapping_sub(self.accounted_at);
+        self.accounted_at = now;
+        elapsed
+    }
+
+    pub fn info(&self, parent: usize) -> ProcessInfo {
+        let mut info = ProcessInfo::empty();
+        info.pid = self.pid;
+        info.parent = parent;
+        info.set_name(&self.name);
+        info.state = match self.state {
+            ProcessState::Blocked | ProcessState::Sleeping(_) if self.stopped => {
+                abi::PROCESS_STATE_STOPPED
+            }
+            ProcessState::Ready => abi::PROCESS_STATE_READY,
+            ProcessState::Running(hartid) => {
+                info.hart = hartid;
+                abi::PROCESS_STATE_RUNNING
+            }
+            ProcessState::Blocked => abi::PROCESS_STATE_BLOCKED,
+            ProcessState::Sleeping(_) => abi::PROCESS_STATE_SLEEPING,
+            ProcessState::Stopped => abi::PROCESS_STATE_STOPPED,
+            ProcessState::Zombie(_) => abi::PROCESS_STATE_ZOMBIE,
+        };
+        info.previous_hart = self.previous_hart;
+        info.created_at = self.created_at;
+        info.cpu_time = self.usage.cpu_time();
+        info.memory = self.memory();
+        info.nice = self.nice;
+        info
+    }
+
+    // True if [address, address + len) is inside the stack of the process
+    pub fn stack_contains(&self, address: usize, len: usize) -> bool {
+        let stack = self.stack as usize;
+        !self.stack.is_null()
+            && address >= stack
+            && address
+                .checked_add(len)
+                .map_or(false, |end| end <= stack + USER_STACK_SIZE)
+    }
+}
+
+// The trap handler saves the context on the user stack with translation
+// off, so user stacks are always identity mapped.
+fn alloc_user_stack(address_space: &AddressSpace) -> usize {
+    let stack = page::zalloc(USER_STACK_PAGES) as usize;
+    assert!(stack as *const u8 != core::ptr::null());
+    address_space.map_identity(
+        stack,
+        stack + USER_STACK_SIZE,
+        PageTableEntryFlags::UserReadWrite,
+    );
+    stack
+}
+
+// Gives the new thread its copy of the thread-locals at the top of its
+// stack. Returns tp (0 without thread-locals) and the new stack end.
+fn push_tls(address_space: &AddressSpace, stack_end: usize) -> (usize, usize) {
+    match address_space.tls.as_ref() {
+        Some(template) => template.push(stack_end),
+        None => (0, stack_end),
+    }
+}
+
+// The first context of a process lives right below its stack top, the same
+// place the trap handler saves it. Returns where the trap frame was copied.
+fn push_trap_frame(context: &mut TrapFrame, stack_top: usize) -> *mut TrapFrame {
+    context.regs[cpu::GeneralPurposeRegister::Sp as usize] =
+        stack_top - core::mem::size_of::<TrapFrame>();
+
+    let trap_frame = context.regs[cpu::GeneralPurposeRegister::Sp as usize] as *mut TrapFrame;
+
+    unsafe {
+        let source = context as *const TrapFrame;
+        core::ptr::copy(source, trap_frame, 1);
+    }
+    trap_frame
+}
+
+// Copies a string with a NUL below top, returns its address
+fn push_string(top: usize, string: &str) -> usize {
+    let address = top - string.len() - 1;
+    unsafe {
+        core::ptr::copy_nonoverlapping(string.as_ptr(), address as *mut u8, string.len());
+        (address as *mut u8).add(string.len()).write(0);
+    }
+    address
+}
+
+// Builds the System V initial stack at the top of an identity mapped stack:
+// sp -> argc, argv[0..argc], NULL, envp[..], NULL, auxv pairs, AT_NULL 0,
+// with the strings above it. Returns the new stack top and the addresses of
+// argv[0] and envp[0].
+fn push_initial_stack(
+    stack_end: usize,
+    argv: &[&str],
+    envp: &[&str],
+    auxiliary_vector: &[(usize, usize)],
+) -> (usize, usize, usize) {
+    let mut top = stack_end;
+    let mut words = Vec::new();
+
+    words.push(argv.len());
+    for argument in argv {
+        top = push_string(top, argument);
+        words.push(top);
+    }
+    words.push(0);
+    for variable in envp {
+        top = push_string(top, variable);
+        words.push(top);
+    }
+    words.push(0);
+    for (kind, value) in auxiliary_vector {
+        words.push(*kind);
+        words.push(*value);
+    }
+    words.push(abi::AT_NULL);
+    words.push(0);
+
+    // sp stays 16-byte aligned
+    let stack_top = (top - words.len() * core::mem::size_of::<usize>()) & !0xf;
+    unsafe {
+        core::ptr::copy_nonoverlapping(words.as_ptr(), stack_top as *mut usize, words.len());
+    }
+
+    let word = core::mem::size_of::<usize>();
+    let argv_address = stack_top + word;
+    let envp_address = argv_address + (argv.len() + 1) * word;
+    (stack_top, argv_address, envp_address)
+}
+
+impl Process {
+    // Frees the stack and drops the reference to the address space, the
+    // page table goes away with the last thread using it.
+    // A zombie only keeps its descriptor.
+    fn release_resources(&mut self) {
+        if !self.stack.is_null() {
+            match self.address_space.as_ref() {
+                Some(address_space) => address_space.retire_stack(self.stack, USER_STACK_SIZE),
+                None => page::dealloc(self.stack),
+            }
+            self.stack = core::ptr::null_mut();
+            self.trap_frame = core::ptr::null_mut();
+        }
+        self.address_space = None;
+    }
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
         debug!("drop pid: {}", self.pid);
-        page::dealloc(self.stack);
-        unsafe { (*self.page_table).unmap() }
-        page::dealloc(self.page_table as *mut u8);
+        self.release_resources();
     }
 }
 
-fn make_user_syscall(_arg0: usize, _arg1: usize, _arg2: usize, _arg3: usize, _arg4: usize) {
+// ecall with the ABI of abi.rs: number in a0, arguments in a1-a5 and the
+// encoded result back in a0
+pub fn user_syscall(number: usize, args: [usize; 5]) -> SyscallResult {
+    let a0: usize;
     unsafe {
-        asm!("ECALL");
+        asm!(
+            "ecall",
+            inlateout("a0") number => a0,
+            in("a1") args[0],
+            in("a2") args[1],
+            in("a3") args[2],
+            in("a4") args[3],
+            in("a5") args[4],
+        );
     }
+    abi::decode(a0)
 }
 
-pub fn create_thread(func: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
-    make_user_syscall(1, func, arg0, arg1, arg2);
-    let pid: usize;
-    unsafe {
-        asm!("mv {}, a0", out(reg) pid);
-    }
-    pid
+// Returns the pid of the new thread
+pub fn create_thread(func: usize, arg0: usize, arg1: usize, arg2: usize) -> SyscallResult {
+    user_syscall(abi::SYS_CREATE_THREAD, [func, arg0, arg1, arg2, 0])
 }
 
-pub fn exit() {
-    make_user_syscall(0, 0, 0, 0, 0);
+pub fn exit(code: usize) -> ! {
+    let _ = user_syscall(abi::SYS_EXIT, [code, 0, 0, 0, 0]);
+    loop {}
 }
 
-pub fn join(pid: usize) {
-    make_user_syscall(2, pid, 0, 0, 0);
+// Waits for pid to exit and returns its exit code,
+// ESRCH if there is no such process to join.
+pub fn join(pid: usize) -> SyscallResult {
+    user_syscall(abi::SYS_JOIN, [pid, 0, 0, 0, 0])
+}
+
+pub fn getpid() -> usize {
+    user_syscall(abi::SYS_GETPID, [0; 5]).unwrap()
+}
+
+pub fn getppid() -> usize {
+    user_syscall(abi::SYS_GETPPID, [0; 5]).unwrap()
 }
 
 pub fn sleep(amount: usize) {
-    make_user_syscall(3, amount, 0, 0, 0);
+    let _ = user_syscall(abi::SYS_SLEEP, [amount, 0, 0, 0, 0]);
+}
+
+// Makes the caller a periodic real-time task, times in context switch
+// periods. Returns the hart it runs on, EBUSY if none can take it.
+pub fn sched_setperiodic(period: usize, wcet: usize, deadline: usize) -> SyscallResult {
+    user_syscall(abi::SYS_SCHED_SETPERIODIC, [period, wcet, deadline, 0, 0])
+}
+
+// Ends the current job and waits for the next period
+pub fn sched_wait_period() {
+    let _ = user_syscall(abi::SYS_SCHED_WAIT_PERIOD, [0; 5]);
+}
+
+// Idle time, context switches and steals of every hart
+pub fn sched_stats() -> Result<Vec<abi::HartStats>, Errno> {
+    let mut stats = Vec::new();
+    stats.resize(cpu::MAX_HARTS, abi::HartStats::default());
+    let harts = user_syscall(
+        abi::SYS_SCHED_STATS,
+        [stats.as_mut_ptr() as usize, stats.len(), 0, 0, 0],
+    )?;
+    stats.truncate(harts);
+    Ok(stats)
+}
+
+// Restricts pid, 0 for the caller, to the harts in mask, bit n for hart n
+pub fn sched_setaffinity(pid: usize, mask: u64) -> Result<(), Errno> {
+    user_syscall(abi::SYS_SCHED_SETAFFINITY, [pid, mask as usize, 0, 0, 0]).map(|_| ())
 }
 
+pub fn sched_getaffinity(pid: usize) -> Result<u64, Errno> {
+    user_syscall(abi::SYS_SCHED_GETAFFINITY, [pid, 0, 0, 0, 0]).map(|mask| mask as u64)
+}
+
+// Scheduling policy of the system, one of abi::SCHED_*
+pub fn sched_getpolicy() -> usize {
+    user_syscall(abi::SYS_SCHED_GETPOLICY, [0; 5]).unwrap()
+}
+
+// Sets the nice value of pid, 0 for the caller
+pub fn nice(pid: usize, nice: isize) -> Result<(), Errno> {
+    user_syscall(abi::SYS_NICE, [pid, nice as usize, 0, 0, 0]).map(|_| ())
+}
+
+// CPU time and scheduling counters of pid, 0 for the caller
+pub fn getrusage(pid: usize) -> Result<ResourceUsage, Errno> {
+    let mut usage = ResourceUsage::new();
+    let buffer = &mut usage as *mut ResourceUsage as usize;
+    user_syscall(abi::SYS_GETRUSAGE, [pid, buffer, 0, 0, 0])?;
+    Ok(usage)
+}
+
+// Longest line read_line returns, the rest of a longer line is dropped
+pub const MAX_LINE: usize = 256;
+
+// Blocks until a line is typed and appends it to buffer
 pub fn read_line(buffer: &mut alloc::string::String) {
-    make_user_syscall(4, buffer as *mut _ as usize, 0, 0, 0);
-    while unsafe { crate::uart::READING } {}
+    let mut line = [0u8; MAX_LINE];
+    let read = user_syscall(
+        abi::SYS_READ_LINE,
+        [line.as_mut_ptr() as usize, line.len(), 0, 0, 0],
+    );
+    if let Ok(len) = read {
+        buffer.push_str(&alloc::string::String::from_utf8_lossy(&line[..len]));
+    }
 }
 
 pub fn print_str(buffer: &str) {
-    make_user_syscall(5, buffer.as_ptr() as usize, buffer.len(), 0, 0);
+    let _ = user_syscall(
+        abi::SYS_PRINT_STR,
+        [buffer.as_ptr() as usize, buffer.len(), 0, 0, 0],
+    );
 }
 
 pub fn time_now() -> usize {
-    make_user_syscall(6, 0, 0, 0, 0);
-    let time: usize;
-    unsafe {
-        asm!("mv {}, a0", out(reg) time);
-    }
-    time
+    user_syscall(abi::SYS_TIME_NOW, [0; 5]).unwrap()
 }
 
-pub fn set_blocking_pid(pid: usize, blocking_pid: usize) {
-    get_pid_list_lock().spin_lock();
+// Sends signal to pid
+pub fn kill(pid: usize, signal: usize) -> Result<(), Errno> {
+    user_syscall(abi::SYS_KILL, [pid, signal, 0, 0, 0]).map(|_| ())
+}
 
-    if let Some((_p, bp)) = pid_list_mut().iter_mut().find(|(p, _)| *p == pid) {
-        *bp = Some(blocking_pid);
-    };
+// Stops pid until it is resumed
+pub fn suspend(pid: usize) -> Result<(), Errno> {
+    kill(pid, signal::SIGSTOP)
+}
 
-    get_pid_list_lock().unlock();
+pub fn resume(pid: usize) -> Result<(), Errno> {
+    kill(pid, signal::SIGCONT)
+}
+
+// Sets the action of signal for the calling thread: signal::SIG_DFL,
+// signal::SIG_IGN or the address of an `extern "C" fn(signal: usize)`.
+pub fn sigaction(signal: usize, handler: usize) -> Result<(), Errno> {
+    let restorer = assembly::__tong_os_signal_restorer as usize;
+    user_syscall(abi::SYS_SIGACTION, [signal, handler, restorer, 0, 0]).map(|_| ())
+}
+
+// Changes the blocked mask (signal::SIG_BLOCK, SIG_UNBLOCK or SIG_SETMASK)
+// and returns the previous one.
+pub fn sigprocmask(how: usize, mask: u64) -> Result<u64, Errno> {
+    user_syscall(abi::SYS_SIGPROCMASK, [how, mask as usize, 0, 0, 0]).map(|old| old as u64)
 }
 
 fn migrate_process(mut process: Process) {
     process.previous_hart = cpu::get_mhartid();
-    let next_hart = scheduler::migration_criteria();
+    let next_hart = scheduler::migrate(&process);
     get_ready_list_lock_by_hartid(next_hart).spin_lock();
 
-    ready_list_by_hartid_mut(next_hart).push_back(process);
+    scheduler::enqueue(next_hart, ready_list_by_hartid_mut(next_hart), process);
     trap::send_software_interrupt(next_hart);
 
     get_ready_list_lock_by_hartid(next_hart).unlock();
@@ -421,57 +941,660 @@ pub fn try_wake_sleeping() -> bool {
     }) {
         woken = true;
 
-        let mut woken = sleeping_list_mut().swap_remove_back(pos).unwrap();
-        woken.state = ProcessState::Ready;
+        let woken = sleeping_list_mut().swap_remove_back(pos).unwrap();
         debug!("woken pid {}", woken.pid);
-        migrate_process(woken);
+        wake_process(woken);
     }
 
     get_sleeping_list_lock().unlock();
     woken
 }
 
-pub fn unblock_process_by_pid(blocked_pid: usize) {
+// Moves a blocked process to a ready list, writing result to its a0
+// (the return value of the syscall it blocked on)
+pub fn unblock_process_by_pid(blocked_pid: usize, result: SyscallResult) {
+    unblock_process_with(blocked_pid, |_| result);
+}
+
+// Same, with a result that needs the blocked process itself (e.g. its page
+// table, to copy data into its buffers)
+pub fn unblock_process_with<F: FnOnce(&Process) -> SyscallResult>(blocked_pid: usize, f: F) {
     get_blocked_list_lock().spin_lock();
     if let Some(pos) = blocked_list().iter().position(|p| p.pid == blocked_pid) {
-        let mut woken = blocked_list_mut().remove(pos).unwrap();
-        woken.state = ProcessState::Ready;
-        migrate_process(woken);
+        let woken = blocked_list_mut().remove(pos).unwrap();
+        let result = f(&woken);
+        unsafe {
+            (*woken.trap_frame).regs[cpu::GeneralPurposeRegister::A0 as usize] =
+                abi::encode(result);
+        }
+        wake_process(woken);
     }
     get_blocked_list_lock().unlock();
 }
 
+// Adds a process started by the kernel, a child of init
 pub fn process_list_add(process: Process) {
+    process_list_add_with_parent(process, INIT_PID);
+}
+
+// Adds a process created by the running process, a child of it
+pub fn child_process_list_add(process: Process) {
+    process_list_add_with_parent(process, get_running_process_pid());
+}
+
+// The new process starts on this hart, unless its affinity rules it out
+fn process_list_add_with_parent(process: Process, parent: usize) {
+    let hartid = if scheduler::allowed(&process, cpu::get_mhartid()) {
+        cpu::get_mhartid()
+    } else {
+        scheduler::migrate(&process)
+    };
     get_pid_list_lock().spin_lock();
-    get_ready_list_lock().spin_lock();
-    debug!("process list add pid {}", process.pid);
+    get_ready_list_lock_by_hartid(hartid).spin_lock();
+    debug!("process list add pid {}, parent {}", process.pid, parent);
 
-    pid_list_mut().push_back((process.pid, None));
-    ready_list_mut().push_back(process);
+    pid_list_mut().push_back(PidEntry::new(process.pid, parent));
+    pid_entry_mut(parent).unwrap().children.push(process.pid);
+    scheduler::enqueue(hartid, ready_list_by_hartid_mut(hartid), process);
 
-    get_ready_list_lock().unlock();
+    get_ready_list_lock_by_hartid(hartid).unlock();
     get_pid_list_lock().unlock();
 }
 
-pub fn pid_list_contains(pid: usize) -> bool {
+// Snapshot of every process that has not been reaped, ordered by pid
+pub fn process_list_snapshot() -> Vec<ProcessInfo> {
+    let mut snapshot = Vec::new();
+    for_each_process(|process, parent| snapshot.push(process.info(parent)));
+    snapshot.sort_by_key(|info| info.pid);
+    snapshot.dedup_by_key(|info| info.pid);
+    snapshot
+}
+
+// Calls f with every process that has not been reaped and its parent.
+// Under the pid lock the process tree cannot change, but each list is
+// locked on its own, so a process moving between lists meanwhile may be
+// seen twice or missed. f is called with a running process under the
+// running lock of its hart, so it may allocate but not take other locks.
+fn for_each_process<F: FnMut(&Process, usize)>(mut f: F) {
     get_pid_list_lock().spin_lock();
+    let mut add = |process: &Process| {
+        if let Some(entry) = pid_entry_mut(process.pid) {
+            f(process, entry.parent);
+        }
+    };
 
-    if let Some((_pid, _bp)) = pid_list()
-        .iter()
-        .find(|(pid_element, _)| *pid_element == pid)
-    {
+    for hartid in 0..running_list().len() {
+        with_running_process(hartid, |running| running.map(&mut add));
+    }
+    for hartid in 0..running_list().len() {
+        get_ready_list_lock_by_hartid(hartid).spin_lock();
+        ready_list_by_hartid(hartid).iter().for_each(&mut add);
+        get_ready_list_lock_by_hartid(hartid).unlock();
+    }
+    get_blocked_list_lock().spin_lock();
+    blocked_list().iter().for_each(&mut add);
+    get_blocked_list_lock().unlock();
+    get_sleeping_list_lock().spin_lock();
+    sleeping_list().iter().for_each(&mut add);
+    get_sleeping_list_lock().unlock();
+    get_stopped_list_lock().spin_lock();
+    stopped_list().iter().for_each(&mut add);
+    get_stopped_list_lock().unlock();
+    zombie_list().iter().for_each(&mut add);
+
+    get_pid_list_lock().unlock();
+}
+
+// Sets the nice value of pid, 0 for the running process
+pub fn set_process_nice(pid: usize, nice: isize) -> Result<(), Errno> {
+    let attributes = Attributes {
+        nice: Some(nice),
+        ..Attributes::default()
+    };
+    update_process(pid, attributes)
+}
+
+// Sets the harts pid may run on, 0 for the running process. A real-time
+// task must keep the hart that admitted it.
+pub fn set_process_affinity(pid: usize, affinity: u64) -> Result<(), Errno> {
+    let attributes = Attributes {
+        affinity: Some(affinity),
+        ..Attributes::default()
+    };
+    update_process(pid, attributes)
+}
+
+pub fn get_process_affinity(pid: usize) -> Option<u64> {
+    if pid == 0 || pid == get_running_process_pid() {
+        return Some(running_process().affinity);
+    }
+    let mut affinity = None;
+    for_each_process(|process, _| {
+        if process.pid == pid {
+            affinity = Some(process.affinity);
+        }
+    });
+    affinity
+}
+
+// Scheduling attributes one process sets on another
+#[derive(Debug, Clone, Copy, Default)]
+struct Attributes {
+    nice: Option<isize>,
+    affinity: Option<u64>,
+}
+
+impl Attributes {
+    // nice and realtime_hart (the hart that admitted it, if it is a
+    // real-time task) are those of the process they are for. Only an
+    // ancestor may lower its nice.
+    fn check(
+        &self,
+        nice: isize,
+        realtime_hart: Option<usize>,
+        ancestor: bool,
+    ) -> Result<(), Errno> {
+        if self.nice.map_or(false, |new| new < nice) && !ancestor {
+            return Err(Errno::EPERM);
+        }
+        match (self.affinity, realtime_hart) {
+            (Some(affinity), Some(hart)) if affinity & (1 << hart) == 0 => Err(Errno::EINVAL),
+            _ => Ok(()),
+        }
+    }
+
+    fn apply(&self, process: &mut Process) {
+        if let Some(nice) = self.nice {
+            process.set_nice(nice);
+        }
+        if let Some(affinity) = self.affinity {
+            process.affinity = affinity;
+        }
+    }
+
+    // These overwritten by later ones
+    fn then(self, later: Attributes) -> Attributes {
+        Attributes {
+            nice: later.nice.or(self.nice),
+            affinity: later.affinity.or(self.affinity),
+        }
+    }
+}
+
+// Applies attributes to pid, 0 for the running process, wherever it is. A
+// ready process is queued again, where its new nice value and affinity put
+// it. A process running on another hart is not touched from here: the
+// attributes wait in its pid entry until its hart traps, which the hart is
+// sent a software interrupt for. Like for_each_process, a process moving
+// between lists meanwhile may be missed and keep its old values.
+fn update_process(pid: usize, attributes: Attributes) -> Result<(), Errno> {
+    let pid = if pid == 0 {
+        get_running_process_pid()
+    } else {
+        pid
+    };
+    get_pid_list_lock().spin_lock();
+    if pid_entry_mut(pid).is_none() {
         get_pid_list_lock().unlock();
-        return true;
+        return Err(Errno::ESRCH);
+    }
+    let caller = get_running_process_pid();
+    let ancestor = pid == caller || is_ancestor(caller, pid);
+
+    // Attributes still waiting from an earlier call go first
+    let pending = take_pending_attributes(pid);
+    let apply: ProcessUpdate = &mut |process| {
+        if let Some(pending) = pending {
+            pending.apply(process);
+        }
+        attributes.check(process.nice, process.realtime_hart(), ancestor)?;
+        attributes.apply(process);
+        Ok(())
+    };
+    let result = update_running(pid, pending, attributes, ancestor)
+        .or_else(|| update_ready(pid, apply))
+        .or_else(|| update_in(get_blocked_list_lock(), blocked_list_mut(), pid, apply))
+        .or_else(|| update_in(get_sleeping_list_lock(), sleeping_list_mut(), pid, apply))
+        .or_else(|| update_in(get_stopped_list_lock(), stopped_list_mut(), pid, apply))
+        .unwrap_or_else(|| {
+            if let Some(pending) = pending {
+                put_pending_attributes(pid, pending);
+            }
+            Ok(())
+        });
+
+    get_pid_list_lock().unlock();
+    result
+}
+
+// Attributes waiting in pid entries, so that traps only take the pid lock
+// when there are some. Guarded by the pid lock.
+static mut PENDING_ATTRIBUTES: usize = 0;
+
+// With the pid lock held
+fn take_pending_attributes(pid: usize) -> Option<Attributes> {
+    let pending = pid_entry_mut(pid).and_then(|entry| entry.attributes.take());
+    if pending.is_some() {
+        unsafe { PENDING_ATTRIBUTES -= 1 };
+    }
+    pending
+}
+
+// With the pid lock held, after take_pending_attributes
+fn put_pending_attributes(pid: usize, attributes: Attributes) {
+    pid_entry_mut(pid).unwrap().attributes = Some(attributes);
+    unsafe { PENDING_ATTRIBUTES += 1 };
+}
+
+// With the pid lock held
+fn update_running(
+    pid: usize,
+    pending: Option<Attributes>,
+    attributes: Attributes,
+    ancestor: bool,
+) -> Option<Result<(), Errno>> {
+    if pid == get_running_process_pid() {
+        let running = running_process_mut();
+        if let Some(pending) = pending {
+            pending.apply(running);
+        }
+        return Some(
+            attributes
+                .check(running.nice, running.realtime_hart(), ancestor)
+                .map(|_| attributes.apply(running)),
+        );
+    }
+
+    // Nice and the real-time hart only change under the pid lock, see
+    // with_running_attributes
+    let (hartid, nice, realtime_hart) = (0..running_list().len()).find_map(|hartid| {
+        with_running_process(hartid, |running| {
+            running
+                .filter(|running| running.pid == pid)
+                .map(|running| (hartid, running.nice, running.realtime_hart()))
+        })
+    })?;
+    let nice = pending.and_then(|pending| pending.nice).unwrap_or(nice);
+    let result = attributes.check(nice, realtime_hart, ancestor);
+    let waiting = match (pending, result) {
+        (Some(pending), Ok(())) => Some(pending.then(attributes)),
+        (None, Ok(())) => Some(attributes),
+        (pending, Err(_)) => pending,
+    };
+    if let Some(waiting) = waiting {
+        put_pending_attributes(pid, waiting);
+        trap::send_software_interrupt(hartid);
+    }
+    Some(result)
+}
+
+fn update_ready(pid: usize, update: ProcessUpdate) -> Option<Result<(), Errno>> {
+    for hartid in 0..running_list().len() {
+        get_ready_list_lock_by_hartid(hartid).spin_lock();
+        let process = ready_list_by_hartid_mut(hartid).remove(pid);
+        get_ready_list_lock_by_hartid(hartid).unlock();
+
+        if let Some(mut process) = process {
+            let result = update(&mut process);
+            if scheduler::allowed(&process, hartid) {
+                get_ready_list_lock_by_hartid(hartid).spin_lock();
+                scheduler::enqueue(hartid, ready_list_by_hartid_mut(hartid), process);
+                get_ready_list_lock_by_hartid(hartid).unlock();
+            } else {
+                migrate_process(process);
+            }
+            return Some(result);
+        }
     }
+    None
+}
+
+type ProcessUpdate<'a> = &'a mut dyn FnMut(&mut Process) -> Result<(), Errno>;
+
+fn update_in(
+    lock: &mut Mutex,
+    list: &mut VecDeque<Process>,
+    pid: usize,
+    update: ProcessUpdate,
+) -> Option<Result<(), Errno>> {
+    lock.spin_lock();
+    let result = list
+        .iter_mut()
+        .find(|process| process.pid == pid)
+        .map(|process| update(process));
+    lock.unlock();
+    result
+}
 
+// Trap entry. Applies the attributes other harts set on the running
+// process while it ran.
+pub fn apply_pending_attributes() {
+    if unsafe { core::ptr::read_volatile(&PENDING_ATTRIBUTES) } == 0 {
+        return;
+    }
+    get_pid_list_lock().spin_lock();
+    apply_running_attributes();
     get_pid_list_lock().unlock();
+}
+
+// With the pid lock held
+fn apply_running_attributes() {
+    let running = running_process_mut();
+    if let Some(pending) = take_pending_attributes(running.pid) {
+        pending.apply(running);
+    }
+}
+
+// Runs f on the running process with the pid lock held and its pending
+// attributes applied. Its real-time parameters change in f, so that
+// update_process checks a new affinity against the right hart.
+pub fn with_running_attributes<R, F: FnOnce(&mut Process) -> R>(f: F) -> R {
+    get_pid_list_lock().spin_lock();
+    apply_running_attributes();
+    let result = f(running_process_mut());
+    get_pid_list_lock().unlock();
+    result
+}
+
+// Parent of pid, None if pid does not exist (or was reaped)
+pub fn get_parent_pid(pid: usize) -> Option<usize> {
+    get_pid_list_lock().spin_lock();
+    let parent = pid_entry_mut(pid).map(|entry| entry.parent);
+    get_pid_list_lock().unlock();
+    parent
+}
+
+// Called with the pid list locked. Init is its own parent.
+fn is_ancestor(ancestor: usize, pid: usize) -> bool {
+    let mut current = pid;
+    while let Some(entry) = pid_entry_mut(current) {
+        if entry.parent == ancestor {
+            return true;
+        }
+        if entry.parent == current {
+            return false;
+        }
+        current = entry.parent;
+    }
     false
 }
 
+// Boot-time W^X audit of every process page table. Threads share their
+// page table, so each one is only walked once.
+pub fn audit_page_tables() {
+    let mut audited: Vec<*const Sv39PageTable> = Vec::new();
+    let mut violations = 0;
+    for hartid in 0..running_list().len() {
+        get_ready_list_lock_by_hartid(hartid).spin_lock();
+        for process in ready_list_by_hartid(hartid).iter() {
+            if let Some(page_table) = process.page_table() {
+                if audited.contains(&(page_table as *const _)) {
+                    continue;
+                }
+                audited.push(page_table);
+                violations += page::audit_page_table(page_table);
+            }
+        }
+        get_ready_list_lock_by_hartid(hartid).unlock();
+    }
+    println!(
+        "W^X audit: {} page table(s), {} violation(s)",
+        audited.len(),
+        violations
+    );
+}
+
+// Marks signal as pending for pid, it is delivered the next time pid
+// returns to user mode, see notify_signal for how it gets there soon.
+// Init can't be signaled.
+pub fn send_signal(pid: usize, signal: usize) -> Result<(), Errno> {
+    if !signal::is_valid(signal) {
+        return Err(Errno::EINVAL);
+    }
+    if pid == INIT_PID {
+        return Err(Errno::EPERM);
+    }
+
+    get_pid_list_lock().spin_lock();
+    let sent = match pid_entry_mut(pid) {
+        Some(entry) => {
+            debug!("signal {} pending for pid {}", signal, pid);
+            // The last of SIGSTOP and SIGCONT wins
+            match signal {
+                signal::SIGSTOP => entry.pending_signals &= !signal::signal_bit(signal::SIGCONT),
+                signal::SIGCONT => entry.pending_signals &= !signal::signal_bit(signal::SIGSTOP),
+                _ => {}
+            }
+            entry.pending_signals |= signal::signal_bit(signal);
+            Ok(())
+        }
+        None => Err(Errno::ESRCH),
+    };
+    if sent.is_ok() {
+        notify_signal(pid, signal);
+    }
+    get_pid_list_lock().unlock();
+    sent
+}
+
+// Makes pid act on a signal that just became pending, wherever it is: a
+// stopped process is continued by SIGCONT and SIGKILL, a blocked or
+// sleeping one is stopped where it waits by SIGSTOP or interrupted, and one
+// running on another hart gets a software interrupt. Ready processes act
+// on it when they are scheduled.
+// Called with the pid list locked.
+fn notify_signal(pid: usize, signal: usize) {
+    match signal {
+        signal::SIGCONT | signal::SIGKILL => continue_stopped_process(pid),
+        signal::SIGSTOP => stop_waiting_process(pid),
+        _ => {}
+    }
+
+    let interrupted = console::cancel_read(pid, || interrupt_blocked_process(pid, signal));
+    if !interrupted {
+        interrupt_sleeping_process(pid, signal);
+    }
+
+    if let Some(hartid) = hart_running(pid) {
+        if hartid != cpu::get_mhartid() {
+            trap::send_software_interrupt(hartid);
+        }
+    }
+}
+
+fn hart_running(pid: usize) -> Option<usize> {
+    (0..running_list().len()).find(|hartid| running_pid(*hartid) == Some(pid))
+}
+
+// An interrupted syscall returns EINTR
+fn interrupt_blocked_process(pid: usize, signal: usize) -> bool {
+    get_blocked_list_lock().spin_lock();
+    let position = blocked_list()
+        .iter()
+        .position(|p| p.pid == pid && p.signals.interrupts(signal));
+    if let Some(position) = position {
+        let interrupted = blocked_list_mut().remove(position).unwrap();
+        unsafe {
+            (*interrupted.trap_frame).regs[cpu::GeneralPurposeRegister::A0 as usize] =
+                abi::encode(Err(Errno::EINTR));
+        }
+        // It may have been joining someone
+        for entry in pid_list_mut().iter_mut() {
+            entry.waiters.retain(|waiter| *waiter != pid);
+        }
+        debug!("pid {} interrupted by signal {}", pid, signal);
+        wake_process(interrupted);
+    }
+    get_blocked_list_lock().unlock();
+    position.is_some()
+}
+
+fn interrupt_sleeping_process(pid: usize, signal: usize) -> bool {
+    get_sleeping_list_lock().spin_lock();
+    let position = sleeping_list()
+        .iter()
+        .position(|p| p.pid == pid && p.signals.interrupts(signal));
+    if let Some(position) = position {
+        let interrupted = sleeping_list_mut().remove(position).unwrap();
+        unsafe {
+            (*interrupted.trap_frame).regs[cpu::GeneralPurposeRegister::A0 as usize] =
+                abi::encode(Err(Errno::EINTR));
+        }
+        debug!("pid {} interrupted by signal {}", pid, signal);
+        wake_process(interrupted);
+    }
+    get_sleeping_list_lock().unlock();
+    position.is_some()
+}
+
+// A blocked or sleeping process is done waiting. One stopped meanwhile
+// goes to the stopped list, its syscall result is kept for SIGCONT.
+// Called with the list it came from locked.
+fn wake_process(mut woken: Process) {
+    if woken.stopped {
+        get_stopped_list_lock().spin_lock();
+        woken.state = ProcessState::Stopped;
+        woken.stopped = false;
+        debug!("pid {} stopped", woken.pid);
+        stopped_list_mut().push_back(woken);
+        get_stopped_list_lock().unlock();
+    } else {
+        woken.state = ProcessState::Ready;
+        migrate_process(woken);
+    }
+}
+
+// SIGSTOP for a blocked or sleeping process: it stays where it is, the
+// syscall it waits on completes as usual once continued
+fn stop_waiting_process(pid: usize) {
+    let stopped = set_waiting_stopped(pid, true);
+    if stopped {
+        if let Some(entry) = pid_entry_mut(pid) {
+            entry.pending_signals &= !signal::signal_bit(signal::SIGSTOP);
+        }
+        debug!("pid {} stopped while waiting", pid);
+    }
+}
+
+// False if pid is neither blocked nor sleeping
+fn set_waiting_stopped(pid: usize, stopped: bool) -> bool {
+    let mut found = false;
+    get_blocked_list_lock().spin_lock();
+    for process in blocked_list_mut().iter_mut().filter(|p| p.pid == pid) {
+        process.stopped = stopped;
+        found = true;
+    }
+    get_blocked_list_lock().unlock();
+
+    get_sleeping_list_lock().spin_lock();
+    for process in sleeping_list_mut().iter_mut().filter(|p| p.pid == pid) {
+        process.stopped = stopped;
+        found = true;
+    }
+    get_sleeping_list_lock().unlock();
+    found
+}
+
+fn continue_stopped_process(pid: usize) {
+    get_stopped_list_lock().spin_lock();
+    if let Some(position) = stopped_list().iter().position(|p| p.pid == pid) {
+        let mut continued = stopped_list_mut().remove(position).unwrap();
+        continued.state = ProcessState::Ready;
+        debug!("pid {} continued", pid);
+        migrate_process(continued);
+    }
+    get_stopped_list_lock().unlock();
+    set_waiting_stopped(pid, false);
+}
+
+// SIGSTOP: parks the running process in the stopped list. False if a
+// SIGCONT or SIGKILL came after the SIGSTOP, then it keeps running.
+pub fn stop_running_process() -> bool {
+    get_pid_list_lock().spin_lock();
+    let pid = get_running_process_pid();
+    let resumed = signal::signal_bit(signal::SIGCONT) | signal::signal_bit(signal::SIGKILL);
+    let stop = pid_entry_mut(pid).map_or(false, |entry| entry.pending_signals & resumed == 0);
+    if stop {
+        get_stopped_list_lock().spin_lock();
+        let mut running = running_process_take();
+        running.state = ProcessState::Stopped;
+        scheduler::on_block(&mut running);
+        debug!("pid {} stopped", pid);
+        stopped_list_mut().push_back(running);
+        get_stopped_list_lock().unlock();
+    }
+    get_pid_list_lock().unlock();
+    stop
+}
+
+// Takes the lowest pending signal of pid that is not blocked
+pub fn take_pending_signal(pid: usize, blocked: u64) -> Option<usize> {
+    get_pid_list_lock().spin_lock();
+    let signal = pid_entry_mut(pid).and_then(|entry| {
+        let deliverable = entry.pending_signals & !blocked;
+        if deliverable == 0 {
+            None
+        } else {
+            let signal = deliverable.trailing_zeros() as usize;
+            entry.pending_signals &= !signal::signal_bit(signal);
+            Some(signal)
+        }
+    });
+    get_pid_list_lock().unlock();
+    signal
+}
+
+// Trap entry, before the kernel can touch the floating point or vector
+// registers
+pub fn save_running_process_extension_state() {
+    let running = running_process_mut();
+    fpu::save_if_dirty(running.pid, running.fp_state.as_mut());
+    vector::save_if_dirty(running.pid, running.vector_state.as_mut());
+}
+
+pub fn enable_running_process_fp() -> bool {
+    fpu::enable_on_first_use(&mut running_process_mut().fp_state)
+}
+
+pub fn enable_running_process_vector() -> bool {
+    vector::enable_on_first_use(&mut running_process_mut().vector_state)
+}
+
+pub fn running_process_stack_contains(address: usize, len: usize) -> bool {
+    running_process().stack_contains(address, len)
+}
+
 pub fn update_running_process_trap_frame(trap_frame: *mut TrapFrame) {
     running_process_mut().trap_frame = trap_frame;
 }
 
+// Trap entry, after update_running_process_trap_frame
+pub fn account_trap_entry() {
+    let running = running_process_mut();
+    let from_user = unsafe { (*running.trap_frame).mode == CpuMode::User as usize };
+    if from_user {
+        running.account_user_time();
+    } else {
+        running.account_kernel_time();
+    }
+}
+
+// Usage of pid, 0 for the running process
+pub fn get_resource_usage(pid: usize) -> Option<ResourceUsage> {
+    if pid == 0 || pid == get_running_process_pid() {
+        let running = running_process_mut();
+        running.account_kernel_time();
+        return Some(running.usage);
+    }
+    let mut usage = None;
+    for_each_process(|process, _| {
+        if process.pid == pid {
+            usage = Some(process.usage);
+        }
+    });
+    usage
+}
+
 pub fn get_running_process_pid() -> usize {
     let pid = running_process().pid;
 
@@ -491,6 +1614,7 @@ pub fn block_process() {
 
     let mut running = running_process_take();
     running.state = ProcessState::Blocked;
+    scheduler::on_block(&mut running);
 
     blocked_list_mut().push_back(running);
 
@@ -503,6 +1627,7 @@ pub fn put_process_to_sleep(until: usize) {
     let mut running = running_process_take();
 
     running.state = ProcessState::Sleeping(until);
+    scheduler::on_block(&mut running);
 
     sleeping_list_mut().push_back(running);
 
@@ -517,49 +1642,115 @@ pub fn yield_idle_process() {
     idle_process_replace(running);
 }
 
-pub fn get_running_process_blocking_pid() -> Option<usize> {
-    get_pid_list_lock().spin_lock();
+pub enum JoinResult {
+    Exited(usize),
+    Blocked,
+    NoSuchProcess,
+}
 
-    let running_pid = get_running_process_pid();
+// Joins pid on behalf of the running process. A zombie is reaped right away,
+// otherwise the running process blocks until pid exits. Any number of
+// processes can join the same pid. Checking the target and blocking happen
+// under the pid list lock, so an exit can't slip in between them.
+pub fn join_process(pid: usize) -> JoinResult {
+    get_pid_list_lock().spin_lock();
 
-    let (_, blocking_pid) = pid_list()
-        .iter()
-        .find(|(pid, _)| *pid == running_pid)
-        .unwrap();
+    let result = if let Some(pos) = zombie_list().iter().position(|p| p.pid == pid) {
+        let zombie = zombie_list_mut().remove(pos).unwrap();
+        reap(pid);
+        match zombie.state {
+            ProcessState::Zombie(exit_code) => JoinResult::Exited(exit_code),
+            state => panic!("pid {} in the zombie list with state {:?}", pid, state),
+        }
+    } else {
+        let running_pid = get_running_process_pid();
+        match pid_entry_mut(pid) {
+            Some(entry) if pid != running_pid && pid != INIT_PID => {
+                entry.waiters.push(running_pid);
+                block_process();
+                JoinResult::Blocked
+            }
+            _ => JoinResult::NoSuchProcess,
+        }
+    };
 
     get_pid_list_lock().unlock();
-    *blocking_pid
+    result
 }
 
-pub fn delete_running_process() {
+// Terminates the running process. Its children are handed to init and
+// every joiner gets the exit code. If nobody was joining, the process stays
+// around as a zombie until someone does, unless its parent is init.
+pub fn exit_running_process(exit_code: usize) {
     get_pid_list_lock().spin_lock();
 
-    let old_running = running_process_take();
-    let pid_list = pid_list_mut();
+    let mut old_running = running_process_take();
+    old_running.release_resources();
+    if let Some(realtime) = old_running.realtime.take() {
+        scheduler::edf::release(&realtime);
+    }
 
-    pid_list.remove(
-        pid_list
-            .iter()
-            .position(|(pid, _)| pid == &old_running.pid)
-            .unwrap(),
-    );
+    let pid = old_running.pid;
+    take_pending_attributes(pid);
+    let entry = pid_entry_mut(pid).unwrap();
+    let children = core::mem::replace(&mut entry.children, Vec::new());
+    let waiters = core::mem::replace(&mut entry.waiters, Vec::new());
+    let parent = entry.parent;
 
-    drop(old_running);
+    for child in children {
+        reparent_to_init(child);
+    }
+
+    if !waiters.is_empty() || parent == INIT_PID {
+        reap(pid);
+        drop(old_running);
+        for waiter in waiters {
+            debug!("waking blocked: {}", waiter);
+            unblock_process_by_pid(waiter, Ok(exit_code));
+        }
+    } else {
+        old_running.state = ProcessState::Zombie(exit_code);
+        zombie_list_mut().push_back(old_running);
+    }
 
     get_pid_list_lock().unlock();
 }
 
+// Orphans are adopted by init. Init reaps its zombies right away, unless
+// someone else is joining them.
+fn reparent_to_init(pid: usize) {
+    debug!("reparenting orphan {} to init", pid);
+    pid_entry_mut(pid).unwrap().parent = INIT_PID;
+    pid_entry_mut(INIT_PID).unwrap().children.push(pid);
+
+    if let Some(pos) = zombie_list().iter().position(|p| p.pid == pid) {
+        zombie_list_mut().remove(pos);
+        reap(pid);
+    }
+}
+
+// Forgets pid for good: drops its entry and unlinks it from its parent
+fn reap(pid: usize) {
+    let pid_list = pid_list_mut();
+    let entry = pid_list
+        .remove(pid_list.iter().position(|entry| entry.pid == pid).unwrap())
+        .unwrap();
+
+    if let Some(parent) = pid_entry_mut(entry.parent) {
+        parent.children.retain(|child| *child != pid);
+    }
+}
+
 pub fn print_process_list() {
     debug!("------ running:");
-    for proc in running_list() {
-        if let Some(proc) = proc {
-            debug!("pid: {} {:?}", proc.pid, proc.state);
-        } else {
-            debug!("None");
+    for hartid in 0..running_list().len() {
+        match running_pid(hartid) {
+            Some(pid) => debug!("pid: {} Running({})", pid, hartid),
+            None => debug!("None"),
         }
     }
     debug!("------ ready:");
-    for proc in ready_list() {
+    for proc in ready_list().iter() {
         debug!("pid: {} {:?}", proc.pid, proc.state);
     }
     debug!("------ blocked:");
@@ -570,6 +1761,14 @@ pub fn print_process_list() {
     for proc in sleeping_list() {
         debug!("pid: {} {:?}", proc.pid, proc.state);
     }
+    debug!("------ stopped:");
+    for proc in stopped_list() {
+        debug!("pid: {} {:?}", proc.pid, proc.state);
+    }
+    debug!("------ zombie:");
+    for proc in zombie_list() {
+        debug!("pid: {} {:?}", proc.pid, proc.state);
+    }
     debug!("------ idle:");
     for proc in unsafe { PROCESS_IDLE.as_ref() } {
         if let Some(proc) = proc {
@@ -581,7 +1780,17 @@ pub fn print_process_list() {
     debug!("-----------");
 }
 
+// Every return to user mode goes through here, so it is where pending
+// signals are delivered and the floating point and vector registers are
+// loaded.
 pub fn switch_to_process(trap_frame: *const TrapFrame) -> ! {
+    let trap_frame = signal::deliver_pending_signals(trap_frame as *mut TrapFrame);
+
+    let running = running_process_mut();
+    fpu::switch_to(running.pid, running.fp_state.as_mut());
+    vector::switch_to(running.pid, running.vector_state.as_mut());
+    running.account_kernel_time();
+
     unsafe { assembly::__tong_os_switch_to_process(trap_frame) }
 }
 
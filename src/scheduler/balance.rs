@@ -0,0 +1,96 @@
+// balance.rs
+// Work stealing between harts
+// tongOS team
+
+// Processes change harts when they become ready, where migrate sends them.
+// On top of that, a hart that runs out of work steals from the busiest
+// ready queue, and every BALANCE_PERIOD a busy hart pulls from the busiest
+// one if it is IMBALANCE or more processes ahead. The other queue is only
+// try-locked while ours is held, so two harts stealing from each other
+// can't deadlock.
+
+use super::ReadyQueue;
+use crate::cpu;
+use crate::process;
+use crate::trap;
+
+// In mtime ticks, 10 ms
+const BALANCE_PERIOD: usize = cpu::FREQ as usize / 100;
+const IMBALANCE: usize = 2;
+
+static mut NEXT_BALANCE: [usize; cpu::MAX_HARTS] = [0; cpu::MAX_HARTS];
+
+// The other hart with the longest ready queue
+fn busiest(hartid: usize) -> Option<(usize, usize)> {
+    (0..process::running_list().len())
+        .filter(|other| *other != hartid)
+        .map(|other| (other, process::ready_list_by_hartid_mut(other).len()))
+        .max_by_key(|(_, len)| *len)
+}
+
+// With the ready queue of hartid locked. An idle hart takes one process
+// from the busiest queue, a busy one half the difference if it is large
+// enough, skipping processes whose affinity excludes hartid. Returns how
+// many moved.
+pub fn pull(hartid: usize, queue: &mut ReadyQueue, idle: bool) -> usize {
+    let threshold = if idle { 1 } else { IMBALANCE };
+    let victim = match busiest(hartid) {
+        Some((victim, len)) if len >= queue.len() + threshold => victim,
+        _ => return 0,
+    };
+    let victim_lock = process::get_ready_list_lock_by_hartid(victim);
+    if !victim_lock.try_lock() {
+        return 0;
+    }
+
+    let victim_queue = process::ready_list_by_hartid_mut(victim);
+    let count = if idle {
+        1
+    } else {
+        victim_queue.len().saturating_sub(queue.len()) / 2
+    };
+    let mut moved = 0;
+    while moved < count {
+        let mut process = match victim_queue.steal(|process| super::allowed(process, hartid)) {
+            Some(process) => process,
+            None => break,
+        };
+        process.previous_hart = victim;
+        debug!(
+            "hart {} steals pid {} from hart {}",
+            hartid, process.pid, victim
+        );
+        super::enqueue(hartid, queue, process);
+        moved += 1;
+    }
+    super::hart_stats_mut(victim).stolen += moved;
+    victim_lock.unlock();
+
+    super::hart_stats_mut(hartid).steals += moved;
+    moved
+}
+
+// Idle hart, true if it found work
+pub fn steal_work() -> bool {
+    let hartid = cpu::get_mhartid();
+    process::get_ready_list_lock().spin_lock();
+    let queue = process::ready_list_mut();
+    let found = !queue.is_empty() || pull(hartid, queue, true) > 0;
+    process::get_ready_list_lock().unlock();
+    found
+}
+
+// Timer interrupt of a busy hart
+pub fn balance_periodically() {
+    let hartid = cpu::get_mhartid();
+    let now = trap::get_mtime() as usize;
+    let next_balance = unsafe { &mut NEXT_BALANCE[hartid] };
+    if now < *next_balance {
+        return;
+    }
+    *next_balance = now + BALANCE_PERIOD;
+
+    process::get_ready_list_lock().spin_lock();
+    pull(hartid, process::ready_list_mut(), false);
+    process::get_ready_list_lock().unlock();
+}
@@ -0,0 +1,41 @@
+// cache.rs
+// Cache affinity: back to the hart a process last ran on
+// tongOS team
+
+// A process that ran recently may still have its working set in the caches
+// of its last hart, so it goes back there unless that hart's load average
+// (load.rs) is IMBALANCE or more above the least loaded hart it may run on.
+// previous_hart is where a process comes from, the waking hart for a
+// sleeper, so the policy looks at the hart it last ran on instead.
+
+use super::{load, Scheduler};
+use crate::abi;
+use crate::cpu;
+use crate::process::{self, Process};
+
+// Two processes, as in balance.rs
+const IMBALANCE: usize = 2 * abi::LOAD_SCALE;
+
+pub struct CacheAffinity;
+
+impl Scheduler for CacheAffinity {
+    fn name(&self) -> &'static str {
+        "cache affinity"
+    }
+
+    fn migrate(&self, process: &Process) -> usize {
+        let least_loaded = (0..process::running_list().len())
+            .filter(|hartid| super::allowed(process, *hartid))
+            .min_by_key(|hartid| load::load(*hartid))
+            .unwrap_or_else(cpu::get_mhartid);
+        match process.last_hart() {
+            Some(last_hart)
+                if super::allowed(process, last_hart)
+                    && load::load(last_hart) < load::load(least_loaded) + IMBALANCE =>
+            {
+                last_hart
+            }
+            _ => least_loaded,
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// cfs.rs
+// Completely fair scheduler
+// tongOS team
+
+// Every process has a virtual runtime: the CPU time it used, scaled down
+// by its weight, so higher priorities age slower. Each hart runs the
+// process with the lowest one, from the tree of its ReadyQueue, for a
+// slice of SCHED_LATENCY shared by weight among the runnable processes.
+// Virtual runtimes only compare within a hart, relative to its
+// min_vruntime, so a process moved to another hart is renormalized.
+
+use super::{ReadyQueue, Scheduler};
+use crate::cpu;
+use crate::process::Process;
+
+// Weight of each nice value, from -20 to 19, as in Linux. Each step is
+// about 10% of CPU time, nice 0 weighs NICE_0_WEIGHT.
+const NICE_TO_WEIGHT: [u64; super::PRIORITY_LEVELS] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+
+// In context switch periods: every runnable process runs once in
+// SCHED_LATENCY, unless there are so many that slices would be shorter
+// than MIN_GRANULARITY
+const SCHED_LATENCY: usize = 6;
+const MIN_GRANULARITY: usize = 1;
+
+// In mtime ticks: how far behind min_vruntime a process that slept is
+// placed, so it runs soon without taking over the hart
+const SLEEPER_CREDIT: u64 = SCHED_LATENCY as u64 * cpu::CONTEXT_SWITCH_TIME / 2;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CfsState {
+    pub vruntime: u64,
+    // Hart whose min_vruntime vruntime is relative to, None until queued
+    hart: Option<usize>,
+    // CPU time already turned into vruntime
+    accounted: usize,
+    slice: usize,
+}
+
+// Only grows, under the ready lock of each hart
+static mut MIN_VRUNTIME: [u64; cpu::MAX_HARTS] = [0; cpu::MAX_HARTS];
+
+fn weight(process: &Process) -> u64 {
+    NICE_TO_WEIGHT[super::nice_level(process.nice)]
+}
+
+// Charges the CPU time used since the last call
+fn update_vruntime(process: &mut Process) {
+    let cpu_time = process.usage.cpu_time();
+    let delta = cpu_time.wrapping_sub(process.cfs.accounted) as u64;
+    process.cfs.accounted = cpu_time;
+    process.cfs.vruntime += delta * NICE_0_WEIGHT / weight(process);
+}
+
+// Moves vruntime from the clock of the hart it was queued on to that of
+// hartid, and keeps a process that slept from coming back too far behind
+fn place(process: &mut Process, hartid: usize) {
+    let min_vruntime = unsafe { MIN_VRUNTIME[hartid] };
+    let vruntime = match process.cfs.hart {
+        Some(hart) if hart != hartid => {
+            let previous_min = unsafe { MIN_VRUNTIME[hart] };
+            (process.cfs.vruntime + min_vruntime).saturating_sub(previous_min)
+        }
+        Some(_) => process.cfs.vruntime,
+        None => min_vruntime,
+    };
+    process.cfs.vruntime = vruntime.max(min_vruntime.saturating_sub(SLEEPER_CREDIT));
+    process.cfs.hart = Some(hartid);
+}
+
+// Picks the hart like LeastBusy
+pub struct Cfs;
+
+impl Scheduler for Cfs {
+    fn name(&self) -> &'static str {
+        "completely fair"
+    }
+
+    fn migrate(&self, process: &Process) -> usize {
+        super::fifo::LeastBusy.migrate(process)
+    }
+
+    // migrate_process lands here with the hart chosen by migrate
+    fn enqueue(&self, hartid: usize, queue: &mut ReadyQueue, mut process: Process) {
+        update_vruntime(&mut process);
+        place(&mut process, hartid);
+        queue.insert_ordered(process.cfs.vruntime, process);
+    }
+
+    fn pick_next(&self, queue: &mut ReadyQueue) -> Option<Process> {
+        let hartid = cpu::get_mhartid();
+        let mut next = queue.pop_lowest_key()?;
+
+        let min_vruntime = unsafe { &mut MIN_VRUNTIME[hartid] };
+        let lowest = queue
+            .lowest_key()
+            .map_or(next.cfs.vruntime, |key| key.min(next.cfs.vruntime));
+        *min_vruntime = (*min_vruntime).max(lowest);
+
+        let total_weight: u64 = queue.iter().map(weight).sum::<u64>() + weight(&next);
+        let runnable = queue.len() + 1;
+        let period = SCHED_LATENCY.max(runnable * MIN_GRANULARITY);
+        let slice = (period as u64 * weight(&next) / total_weight) as usize;
+        next.cfs.slice = slice.max(MIN_GRANULARITY);
+        // Processes queued by another policy join this hart's clock now
+        if next.cfs.hart != Some(hartid) {
+            place(&mut next, hartid);
+        }
+        Some(next)
+    }
+
+    fn quantum(&self, process: &Process) -> usize {
+        process.cfs.slice.max(MIN_GRANULARITY)
+    }
+
+    fn on_block(&self, process: &mut Process) {
+        update_vruntime(process);
+    }
+}
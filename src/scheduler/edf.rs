@@ -0,0 +1,131 @@
+// edf.rs
+// Earliest deadline first, for periodic real-time tasks
+// tongOS team
+
+// A periodic task releases a job every period, and each job needs up to
+// wcet of CPU time before its deadline, relative to the release.
+// Real-time tasks run before every other process, whatever the policy,
+// earliest absolute deadline first, and stay on the hart that admitted
+// them. A hart admits a task while the densities wcet / deadline of its
+// tasks add up to at most 1, the bound under which EDF meets every
+// deadline. Misses are still checked, a task may use more than its wcet.
+
+use crate::abi::Errno;
+use crate::cpu;
+use crate::lock::Mutex;
+use crate::process::Process;
+use crate::trap;
+
+// Times in mtime ticks
+#[derive(Debug, Clone, Copy)]
+pub struct RealTime {
+    pub period: usize,
+    pub wcet: usize,
+    pub deadline: usize,
+    // Hart that admitted it
+    pub hart: usize,
+    // Of the current job
+    pub release: usize,
+    pub absolute_deadline: usize,
+    // The current job was already counted as a miss
+    missed: bool,
+}
+
+// Densities in millionths
+const DENSITY_SCALE: usize = 1_000_000;
+
+// Keeps wcet * DENSITY_SCALE and the release times far from overflowing,
+// about 30 hours at 10 MHz
+const MAX_PERIOD: usize = 1 << 40;
+
+static mut UTILIZATION: [usize; cpu::MAX_HARTS] = [0; cpu::MAX_HARTS];
+static mut UTILIZATION_LOCK: Mutex = Mutex::new();
+
+fn density(wcet: usize, deadline: usize) -> usize {
+    wcet * DENSITY_SCALE / deadline
+}
+
+// Finds a hart for the task among those in affinity, first fit. previous
+// is the task being replaced, if it was periodic already. EBUSY if no hart
+// has room left.
+pub fn admit(
+    period: usize,
+    wcet: usize,
+    deadline: usize,
+    affinity: u64,
+    previous: Option<&RealTime>,
+) -> Result<RealTime, Errno> {
+    if wcet == 0 || wcet > deadline || deadline > period || period > MAX_PERIOD {
+        return Err(Errno::EINVAL);
+    }
+    let needed = density(wcet, deadline);
+
+    unsafe { UTILIZATION_LOCK.spin_lock() };
+    let utilization = unsafe { &mut UTILIZATION };
+    if let Some(previous) = previous {
+        utilization[previous.hart] -= density(previous.wcet, previous.deadline);
+    }
+    let hart = utilization
+        .iter()
+        .enumerate()
+        .position(|(hart, used)| affinity & (1 << hart) != 0 && used + needed <= DENSITY_SCALE);
+    match hart {
+        Some(hart) => utilization[hart] += needed,
+        None => {
+            if let Some(previous) = previous {
+                utilization[previous.hart] += density(previous.wcet, previous.deadline);
+            }
+        }
+    }
+    unsafe { UTILIZATION_LOCK.unlock() };
+
+    let hart = hart.ok_or(Errno::EBUSY)?;
+    let release = trap::get_mtime() as usize;
+    Ok(RealTime {
+        period,
+        wcet,
+        deadline,
+        hart,
+        release,
+        absolute_deadline: release + deadline,
+        missed: false,
+    })
+}
+
+// The task exited or is not periodic anymore
+pub fn release(realtime: &RealTime) {
+    unsafe {
+        UTILIZATION_LOCK.spin_lock();
+        UTILIZATION[realtime.hart] -= density(realtime.wcet, realtime.deadline);
+        UTILIZATION_LOCK.unlock();
+    }
+}
+
+// Counts and logs a miss of the current job once
+pub fn check_deadline(process: &mut Process) {
+    let now = trap::get_mtime() as usize;
+    if let Some(realtime) = process.realtime.as_mut() {
+        if !realtime.missed && now > realtime.absolute_deadline {
+            realtime.missed = true;
+            process.usage.deadline_misses += 1;
+            println!(
+                "edf: pid {} missed the deadline of job {} by {} ticks",
+                process.pid,
+                process.usage.jobs + 1,
+                now - realtime.absolute_deadline
+            );
+        }
+    }
+}
+
+// The current job is done. Returns when the next one is released, which
+// may be already.
+pub fn complete_job(process: &mut Process) -> usize {
+    check_deadline(process);
+    process.usage.jobs += 1;
+    let realtime = process.realtime.as_mut().unwrap();
+    realtime.release += realtime.period;
+    realtime.absolute_deadline = realtime.release + realtime.deadline;
+    realtime.missed = false;
+    realtime.release
+}
@@ -0,0 +1,82 @@
+// fifo.rs
+// FIFO ready queues, differing only in the hart a ready process goes to
+// tongOS team
+
+use super::Scheduler;
+use crate::cpu;
+use crate::lock::Mutex;
+use crate::process::{self, Process};
+
+// Hart after the current one, among those the process may run on
+pub struct NextHart;
+
+impl Scheduler for NextHart {
+    fn name(&self) -> &'static str {
+        "next hart"
+    }
+
+    fn migrate(&self, process: &Process) -> usize {
+        let hartid = cpu::get_mhartid();
+        (1..=cpu::MAX_HARTS)
+            .map(|offset| (hartid + offset) % cpu::MAX_HARTS)
+            .find(|next_hart| super::allowed(process, *next_hart))
+            .unwrap_or(hartid)
+    }
+}
+
+static mut NEXT_HART: usize = 0;
+static mut NEXT_HART_MUTEX: Mutex = Mutex::new();
+
+// Every hart in turn, shared by all harts. Harts the process may not run
+// on are skipped.
+pub struct RoundRobin;
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round robin"
+    }
+
+    fn migrate(&self, process: &Process) -> usize {
+        unsafe {
+            NEXT_HART_MUTEX.spin_lock();
+
+            let next_hart = {
+                while !super::allowed(process, NEXT_HART) {
+                    NEXT_HART = (NEXT_HART + 1) % cpu::MAX_HARTS;
+                }
+                let next_hart = NEXT_HART;
+                NEXT_HART = (NEXT_HART + 1) % cpu::MAX_HARTS;
+                next_hart
+            };
+
+            NEXT_HART_MUTEX.unlock();
+            next_hart
+        }
+    }
+}
+
+// An idle hart if there is one, otherwise the shortest ready queue, among
+// those the process may run on
+pub struct LeastBusy;
+
+impl Scheduler for LeastBusy {
+    fn name(&self) -> &'static str {
+        "least busy"
+    }
+
+    fn migrate(&self, process: &Process) -> usize {
+        let mut least = core::usize::MAX;
+        let mut least_hartid = cpu::get_mhartid();
+        for hartid in (0..cpu::MAX_HARTS).filter(|hartid| super::allowed(process, *hartid)) {
+            if process::running_pid(hartid) == Some(process::IDLE_ID) {
+                return hartid;
+            }
+            let len = process::ready_list_by_hartid_mut(hartid).len();
+            if len < least {
+                least = len;
+                least_hartid = hartid;
+            }
+        }
+        least_hartid
+    }
+}
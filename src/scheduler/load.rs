@@ -0,0 +1,43 @@
+// load.rs
+// Load average of every hart
+// tongOS team
+
+// The load of a hart is an exponentially weighted moving average of its
+// run queue length, the ready processes plus the running one unless it is
+// idle, in abi::LOAD_SCALE fractions of a process. Every hart samples its
+// own queue once per LOAD_PERIOD, from its timer interrupt, busy or idle.
+// Other harts read it without a lock, like the ready queue lengths.
+
+use crate::abi;
+use crate::cpu;
+use crate::process;
+use crate::trap;
+
+// In mtime ticks, 10 ms
+const LOAD_PERIOD: usize = cpu::FREQ as usize / 100;
+// Weight of the previous average, 7/8, so a sample counts for about 80 ms
+const DECAY: usize = abi::LOAD_SCALE * 7 / 8;
+
+static mut LOAD: [usize; cpu::MAX_HARTS] = [0; cpu::MAX_HARTS];
+static mut NEXT_SAMPLE: [usize; cpu::MAX_HARTS] = [0; cpu::MAX_HARTS];
+
+pub fn sample_load() {
+    let hartid = cpu::get_mhartid();
+    let now = trap::get_mtime() as usize;
+    let next_sample = unsafe { &mut NEXT_SAMPLE[hartid] };
+    if now < *next_sample {
+        return;
+    }
+    *next_sample = now + LOAD_PERIOD;
+
+    let running = process::running_list()[hartid]
+        .as_ref()
+        .map_or(false, |running| running.pid != process::IDLE_ID);
+    let length = process::ready_list_by_hartid_mut(hartid).len() + running as usize;
+    let average = load(hartid) * DECAY + length * abi::LOAD_SCALE * (abi::LOAD_SCALE - DECAY);
+    unsafe { core::ptr::write_volatile(&mut LOAD[hartid], average / abi::LOAD_SCALE) };
+}
+
+pub fn load(hartid: usize) -> usize {
+    unsafe { core::ptr::read_volatile(&LOAD[hartid]) }
+}
@@ -0,0 +1,88 @@
+// mlfq.rs
+// Multi-level feedback queue
+// tongOS team
+
+// A process starts at the top level. Using up its quantum moves it one
+// level down, where quanta are twice as long; blocking or sleeping before
+// that moves it one level up. Every BOOST_PERIOD every process goes back to
+// the top, so CPU-bound ones can't starve. Levels are those of the
+// ReadyQueue, nice values are not used.
+
+use super::{ReadyQueue, Scheduler};
+use crate::cpu;
+use crate::process::Process;
+use crate::trap;
+
+const MLFQ_LEVELS: usize = 4;
+
+// Quantum of the top level, in context switch periods
+const BASE_QUANTUM: usize = 1;
+
+// In mtime ticks, 1 second
+const BOOST_PERIOD: usize = cpu::FREQ as usize;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MlfqState {
+    pub level: usize,
+    // Boost period it was last queued in
+    epoch: usize,
+}
+
+// Boost period of the last time each hart's queue was boosted
+static mut QUEUE_EPOCH: [usize; cpu::MAX_HARTS] = [0; cpu::MAX_HARTS];
+
+fn current_epoch() -> usize {
+    trap::get_mtime() as usize / BOOST_PERIOD
+}
+
+// Back to the top if a boost happened since it was queued
+fn refresh(process: &mut Process, epoch: usize) {
+    if process.mlfq.epoch != epoch {
+        process.mlfq.level = 0;
+        process.mlfq.epoch = epoch;
+    }
+}
+
+// Picks the hart like RoundRobin
+pub struct Mlfq;
+
+impl Scheduler for Mlfq {
+    fn name(&self) -> &'static str {
+        "multi-level feedback queue"
+    }
+
+    fn migrate(&self, process: &Process) -> usize {
+        super::fifo::RoundRobin.migrate(process)
+    }
+
+    fn enqueue(&self, _hartid: usize, queue: &mut ReadyQueue, mut process: Process) {
+        refresh(&mut process, current_epoch());
+        queue.push_back(process.mlfq.level, process);
+    }
+
+    fn pick_next(&self, queue: &mut ReadyQueue) -> Option<Process> {
+        let epoch = current_epoch();
+        let queue_epoch = unsafe { &mut QUEUE_EPOCH[cpu::get_mhartid()] };
+        if *queue_epoch != epoch {
+            for mut process in queue.drain() {
+                refresh(&mut process, epoch);
+                queue.push_back(process.mlfq.level, process);
+            }
+            *queue_epoch = epoch;
+        }
+        queue.pop_highest()
+    }
+
+    fn quantum(&self, process: &Process) -> usize {
+        BASE_QUANTUM << process.mlfq.level.min(MLFQ_LEVELS - 1)
+    }
+
+    fn tick(&self, running: &mut Process) -> bool {
+        running.mlfq.level = (running.mlfq.level + 1).min(MLFQ_LEVELS - 1);
+        true
+    }
+
+    fn on_block(&self, process: &mut Process) {
+        process.mlfq.level = process.mlfq.level.saturating_sub(1);
+    }
+}
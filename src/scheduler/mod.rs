@@ -0,0 +1,244 @@
+// scheduler/mod.rs
+// Simple process scheduler
+// Stephen Marz
+// tongOs team
+
+// The policy is a Scheduler, picked at boot by crate::SCHEDULER and
+// changed at runtime with the sched_setpolicy syscall. The rest of the
+// kernel goes through the functions below, which run periodic real-time
+// tasks (edf.rs) ahead of the policy. Each hart has its
+// own ready queue (process.rs); the policy decides which queue a ready
+// process goes to, where in it and which process of it runs next. Policies
+// may switch while processes are queued, so a queue must make sense to any
+// of them: pick_next looks at what is queued rather than trusting its
+// order. A queue holds one FIFO per priority level and a tree ordered by a
+// key (queue.rs).
+
+mod balance;
+mod cache;
+mod cfs;
+pub mod edf;
+mod fifo;
+mod load;
+mod mlfq;
+mod queue;
+
+pub use balance::steal_work;
+pub use cfs::CfsState;
+pub use edf::RealTime;
+pub use load::sample_load;
+pub use mlfq::MlfqState;
+pub use queue::{nice_level, ReadyQueue, PRIORITY_LEVELS};
+
+use crate::abi::{self, Errno, HartStats};
+use crate::cpu::{self, TrapFrame};
+use crate::process::{self, Process, ProcessState};
+use crate::trap;
+
+use alloc::vec::Vec;
+
+pub trait Scheduler: Sync {
+    fn name(&self) -> &'static str;
+
+    // Hart whose ready queue gets process, which became ready on this hart:
+    // it was preempted, created, woken up or continued.
+    fn migrate(&self, process: &Process) -> usize;
+
+    // Puts process in the ready queue of hartid, with that queue locked.
+    // By default at the back of the level of its nice value.
+    fn enqueue(&self, _hartid: usize, queue: &mut ReadyQueue, process: Process) {
+        queue.push_back(nice_level(process.nice), process);
+    }
+
+    // Takes the process to run next on this hart from its locked ready
+    // queue, None to run idle
+    fn pick_next(&self, queue: &mut ReadyQueue) -> Option<Process> {
+        queue.pop_highest()
+    }
+
+    // Context switch periods process runs before the timer interrupt
+    fn quantum(&self, process: &Process) -> usize {
+        process.quantum
+    }
+
+    // Timer interrupt, the running process used up its quantum. True to
+    // preempt it, false to give it another quantum.
+    fn tick(&self, _running: &mut Process) -> bool {
+        true
+    }
+
+    // The running process leaves the hart without being ready: it blocked,
+    // went to sleep or was stopped
+    fn on_block(&self, _process: &mut Process) {}
+}
+
+// Indexed by abi::SCHED_*
+static SCHEDULERS: [&dyn Scheduler; abi::SCHED_POLICY_COUNT] = [
+    &fifo::LeastBusy,
+    &fifo::RoundRobin,
+    &fifo::NextHart,
+    &mlfq::Mlfq,
+    &cfs::Cfs,
+    &cache::CacheAffinity,
+];
+
+static mut POLICY: usize = crate::SCHEDULER;
+
+// Each hart's entry is updated under its ready lock, except idle_time that
+// only the hart itself writes
+static mut HART_STATS: [HartStats; cpu::MAX_HARTS] = [HartStats {
+    idle_time: 0,
+    context_switches: 0,
+    steals: 0,
+    stolen: 0,
+    migrations: 0,
+    load: 0,
+}; cpu::MAX_HARTS];
+
+fn hart_stats_mut(hartid: usize) -> &'static mut HartStats {
+    unsafe { &mut HART_STATS[hartid] }
+}
+
+// The idle process of this hart was charged, idle_time is its CPU time so
+// far and only grows
+pub fn record_idle_time(idle_time: usize) {
+    hart_stats_mut(cpu::get_mhartid()).idle_time = idle_time;
+}
+
+pub fn hart_stats() -> Vec<HartStats> {
+    (0..process::running_list().len())
+        .map(|hartid| HartStats {
+            load: load::load(hartid),
+            ..unsafe { HART_STATS[hartid] }
+        })
+        .collect()
+}
+
+pub fn current() -> &'static dyn Scheduler {
+    SCHEDULERS[get_policy()]
+}
+
+pub fn get_policy() -> usize {
+    unsafe { core::ptr::read_volatile(&POLICY) }
+}
+
+// Returns the previous policy
+pub fn set_policy(policy: usize) -> Result<usize, Errno> {
+    if policy >= SCHEDULERS.len() {
+        return Err(Errno::EINVAL);
+    }
+    let previous = get_policy();
+    unsafe { core::ptr::write_volatile(&mut POLICY, policy) };
+    debug!(
+        "scheduler: {} -> {}",
+        SCHEDULERS[previous].name(),
+        SCHEDULERS[policy].name()
+    );
+    Ok(previous)
+}
+
+// Whether the affinity of process lets it run on hartid
+pub fn allowed(process: &Process, hartid: usize) -> bool {
+    process.affinity & (1 << hartid) != 0
+}
+
+pub fn migrate(process: &Process) -> usize {
+    match process.realtime {
+        Some(realtime) => realtime.hart,
+        None => current().migrate(process),
+    }
+}
+
+pub fn enqueue(hartid: usize, queue: &mut ReadyQueue, process: Process) {
+    match process.realtime {
+        Some(realtime) => queue.insert_realtime(realtime.absolute_deadline, process),
+        None => current().enqueue(hartid, queue, process),
+    }
+}
+
+pub fn pick_next(queue: &mut ReadyQueue) -> Option<Process> {
+    queue.pop_realtime().or_else(|| current().pick_next(queue))
+}
+
+pub fn quantum(process: &Process) -> usize {
+    match process.realtime {
+        Some(_) => 1,
+        None => current().quantum(process),
+    }
+}
+
+// Real-time tasks are preempted every quantum, so that an earlier deadline
+// gets the hart
+pub fn tick(running: &mut Process) -> bool {
+    load::sample_load();
+    balance::balance_periodically();
+    match running.realtime {
+        Some(_) => {
+            edf::check_deadline(running);
+            true
+        }
+        None => current().tick(running),
+    }
+}
+
+pub fn on_block(process: &mut Process) {
+    if process.realtime.is_none() {
+        current().on_block(process);
+    }
+}
+
+// A real-time task was queued on this hart, true if it should take the hart
+// from the running process
+pub fn preempts_running() -> bool {
+    process::get_ready_list_lock().spin_lock();
+    let earliest = process::ready_list_mut().earliest_deadline();
+    process::get_ready_list_lock().unlock();
+
+    let running = process::running_process();
+    match (earliest, running.realtime) {
+        (None, _) => false,
+        (Some(_), None) => running.pid != process::IDLE_ID,
+        (Some(earliest), Some(realtime)) => earliest < realtime.absolute_deadline,
+    }
+}
+
+pub fn schedule() -> ! {
+    process::get_ready_list_lock().spin_lock();
+    debug!("running schedule");
+
+    let queue = process::ready_list_mut();
+    if queue.is_empty() {
+        balance::pull(cpu::get_mhartid(), queue, true);
+    }
+    if let Some(next) = pick_next(queue) {
+        debug!("scheduling pid {}", next.pid);
+        let (trap_frame, quantum) = prepare_running_process(next);
+
+        process::get_ready_list_lock().unlock();
+
+        trap::schedule_machine_timer_interrupt(quantum);
+        process::switch_to_process(trap_frame);
+    } else {
+        debug!("scheduling idle");
+        let idle = process::idle_process_take();
+        let (trap_frame, quantum) = prepare_running_process(idle);
+
+        process::get_ready_list_lock().unlock();
+
+        trap::schedule_machine_timer_interrupt(quantum);
+        process::switch_to_process(trap_frame);
+    }
+}
+
+fn prepare_running_process(mut next: Process) -> (*const TrapFrame, usize) {
+    let hartid = cpu::get_mhartid();
+    next.state = ProcessState::Running(hartid);
+    if next.account_switch_to(hartid) {
+        hart_stats_mut(hartid).migrations += 1;
+    }
+    hart_stats_mut(hartid).context_switches += 1;
+    let trap_frame = next.trap_frame;
+    let quantum = quantum(&next);
+    process::running_process_replace(next);
+    (trap_frame, quantum)
+}
@@ -0,0 +1,161 @@
+// queue.rs
+// Per-hart ready queue
+// tongOS team
+
+use crate::abi;
+use crate::process::Process;
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+
+// One FIFO per priority level, level 0 runs first
+pub const PRIORITY_LEVELS: usize = (abi::NICE_MAX - abi::NICE_MIN + 1) as usize;
+
+// Processes are either in a level or, for policies that order them by a
+// key (the CFS virtual runtime), in the tree. After a policy switch both
+// may hold processes, pop_highest takes the levels first. Real-time tasks
+// are apart, whatever the policy.
+pub struct ReadyQueue {
+    levels: Vec<VecDeque<Process>>,
+    // Ordered by (key, pid)
+    tree: BTreeMap<(u64, usize), Process>,
+    // Ordered by (absolute deadline, pid)
+    realtime: BTreeMap<(usize, usize), Process>,
+    len: usize,
+}
+
+impl ReadyQueue {
+    pub fn new() -> Self {
+        let mut levels = Vec::with_capacity(PRIORITY_LEVELS);
+        levels.resize_with(PRIORITY_LEVELS, VecDeque::new);
+        ReadyQueue {
+            levels,
+            tree: BTreeMap::new(),
+            realtime: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_back(&mut self, level: usize, process: Process) {
+        self.levels[level].push_back(process);
+        self.len += 1;
+    }
+
+    pub fn insert_ordered(&mut self, key: u64, process: Process) {
+        self.tree.insert((key, process.pid), process);
+        self.len += 1;
+    }
+
+    // Front of the highest non-empty level, or the lowest key
+    pub fn pop_highest(&mut self) -> Option<Process> {
+        self.pop_level().or_else(|| self.pop_tree())
+    }
+
+    // Lowest key in the tree, or the front of the highest non-empty level
+    pub fn pop_lowest_key(&mut self) -> Option<Process> {
+        self.pop_tree().or_else(|| self.pop_level())
+    }
+
+    fn pop_level(&mut self) -> Option<Process> {
+        let level = self.levels.iter().position(|level| !level.is_empty())?;
+        self.len -= 1;
+        self.levels[level].pop_front()
+    }
+
+    fn pop_tree(&mut self) -> Option<Process> {
+        let key = *self.tree.keys().next()?;
+        self.len -= 1;
+        self.tree.remove(&key)
+    }
+
+    pub fn insert_realtime(&mut self, absolute_deadline: usize, process: Process) {
+        self.realtime
+            .insert((absolute_deadline, process.pid), process);
+        self.len += 1;
+    }
+
+    pub fn pop_realtime(&mut self) -> Option<Process> {
+        let key = *self.realtime.keys().next()?;
+        self.len -= 1;
+        self.realtime.remove(&key)
+    }
+
+    pub fn earliest_deadline(&self) -> Option<usize> {
+        self.realtime.keys().next().map(|(deadline, _)| *deadline)
+    }
+
+    pub fn lowest_key(&self) -> Option<u64> {
+        self.tree.keys().next().map(|(key, _)| *key)
+    }
+
+    // The process that would wait the longest among those allowed, for
+    // another hart to take. Real-time tasks are pinned and never stolen.
+    pub fn steal<F: Fn(&Process) -> bool>(&mut self, allowed: F) -> Option<Process> {
+        for level in self.levels.iter_mut().rev() {
+            if let Some(position) = level.iter().rposition(|process| allowed(process)) {
+                self.len -= 1;
+                return level.remove(position);
+            }
+        }
+        let key = *self
+            .tree
+            .iter()
+            .rev()
+            .find(|(_, process)| allowed(process))?
+            .0;
+        self.len -= 1;
+        self.tree.remove(&key)
+    }
+
+    pub fn remove(&mut self, pid: usize) -> Option<Process> {
+        for level in self.levels.iter_mut() {
+            if let Some(position) = level.iter().position(|process| process.pid == pid) {
+                self.len -= 1;
+                return level.remove(position);
+            }
+        }
+        if let Some(key) = self.tree.keys().find(|(_, key_pid)| *key_pid == pid) {
+            let key = *key;
+            self.len -= 1;
+            return self.tree.remove(&key);
+        }
+        let key = *self.realtime.keys().find(|(_, key_pid)| *key_pid == pid)?;
+        self.len -= 1;
+        self.realtime.remove(&key)
+    }
+
+    // Takes every process out of the levels and the tree, highest level
+    // first. Real-time tasks stay.
+    pub fn drain(&mut self) -> Vec<Process> {
+        let mut processes = Vec::with_capacity(self.len);
+        for level in self.levels.iter_mut() {
+            processes.extend(level.drain(..));
+        }
+        let tree = core::mem::replace(&mut self.tree, BTreeMap::new());
+        processes.extend(tree.into_iter().map(|(_, process)| process));
+        self.len = self.realtime.len();
+        processes
+    }
+
+    // Real-time tasks by deadline, then highest level first, then by key
+    pub fn iter(&self) -> impl Iterator<Item = &Process> {
+        self.realtime
+            .values()
+            .chain(self.levels.iter().flatten())
+            .chain(self.tree.values())
+    }
+}
+
+// Level of a nice value, the lowest nice runs first
+pub fn nice_level(nice: isize) -> usize {
+    (nice - abi::NICE_MIN) as usize
+}
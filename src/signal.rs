@@ -0,0 +1,346 @@
+// signal.rs
+// POSIX-like signals
+// tongOS team
+
+// Pending signals are kept in the pid list (see process::send_signal), so
+// they can be raised for a process wherever it is. They are delivered on
+// the way back to user mode (process::switch_to_process). A handler runs on
+// the user stack on top of a SignalFrame holding the interrupted context,
+// and returns through a restorer that calls sigreturn.
+// A signal that would be acted upon also interrupts a blocked or sleeping
+// process, its syscall returns EINTR. SIGSTOP parks a process in the
+// stopped list until SIGCONT, neither can be caught.
+
+use crate::cpu::{self, GeneralPurposeRegister, TrapFrame};
+use crate::fpu::FloatingPointState;
+use crate::process;
+use crate::scheduler;
+
+// Numbers and sigaction/sigprocmask constants are part of the syscall ABI
+pub use crate::abi::{
+    NSIG, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGHUP, SIGILL, SIGINT, SIGKILL,
+    SIGNAL_EXIT_BASE, SIGPIPE, SIGQUIT, SIGSEGV, SIGSTOP, SIGSYS, SIGTERM, SIGTRAP, SIGUSR1,
+    SIGUSR2, SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, SIG_UNBLOCK,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalAction {
+    Default,
+    Ignore,
+    Handler { handler: usize, restorer: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    // Handled when sent (see process::send_signal), ignored on delivery
+    Continue,
+}
+
+pub fn default_action(signal: usize) -> DefaultAction {
+    match signal {
+        SIGCHLD => DefaultAction::Ignore,
+        SIGSTOP => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+pub fn is_valid(signal: usize) -> bool {
+    signal > 0 && signal < NSIG
+}
+
+// SIGKILL and SIGSTOP can't be caught, ignored or blocked
+fn is_catchable(signal: usize) -> bool {
+    is_valid(signal) && signal != SIGKILL && signal != SIGSTOP
+}
+
+pub const fn signal_bit(signal: usize) -> u64 {
+    1 << signal
+}
+
+const UNBLOCKABLE: u64 = signal_bit(SIGKILL) | signal_bit(SIGSTOP);
+
+// Per thread signal state. Pending signals are in the pid list.
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    pub blocked: u64,
+    pub actions: [SignalAction; NSIG],
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        SignalState {
+            blocked: 0,
+            actions: [SignalAction::Default; NSIG],
+        }
+    }
+
+    pub fn set_action(&mut self, signal: usize, action: SignalAction) -> bool {
+        if !is_catchable(signal) {
+            return false;
+        }
+        self.actions[signal] = action;
+        true
+    }
+
+    // True if signal makes a blocked or sleeping process give up its
+    // syscall: it is not blocked and terminates or runs a handler
+    pub fn interrupts(&self, signal: usize) -> bool {
+        if self.blocked & signal_bit(signal) != 0 {
+            return false;
+        }
+        match self.actions[signal] {
+            SignalAction::Ignore => false,
+            SignalAction::Default => default_action(signal) == DefaultAction::Terminate,
+            SignalAction::Handler { .. } => true,
+        }
+    }
+
+    // Returns the mask before the change
+    pub fn change_blocked(&mut self, how: usize, mask: u64) -> Option<u64> {
+        let old = self.blocked;
+        self.blocked = match how {
+            SIG_BLOCK => old | mask,
+            SIG_UNBLOCK => old & !mask,
+            SIG_SETMASK => mask,
+            _ => return None,
+        } & !UNBLOCKABLE;
+        Some(old)
+    }
+}
+
+// What the handler finds above its stack pointer. sigreturn restores
+// trap_frame and the blocked mask from it, and the floating point registers
+// (in trap_frame.fregs) and vector registers since the handler may use them
+// too.
+#[repr(C)]
+pub struct SignalFrame {
+    pub trap_frame: TrapFrame,
+    pub blocked: u64,
+    pub signal: usize,
+    // 0 if the process never used the FPU
+    pub fp_used: usize,
+    pub fcsr: u64,
+    // Bytes of vector registers right above the frame, 0 if the process
+    // never used the vector unit
+    pub vector_size: usize,
+    pub vector_csrs: [usize; 4],
+}
+
+// Synchronous exception causes (mcause) raised by a faulting user process
+pub fn fault_signal(cause: usize) -> usize {
+    match cause {
+        // Illegal instruction
+        2 => SIGILL,
+        // Breakpoint
+        3 => SIGTRAP,
+        // Load and store/AMO address misaligned
+        4 | 6 => SIGBUS,
+        // Instruction address misaligned, access faults and page faults
+        _ => SIGSEGV,
+    }
+}
+
+// A fault can't be blocked or ignored: returning to the faulting
+// instruction would fault again. Falls back to the default action.
+pub fn force_signal(signal: usize) {
+    let signals = &mut process::running_process_mut().signals;
+    signals.blocked &= !signal_bit(signal);
+    if signals.actions[signal] == SignalAction::Ignore {
+        signals.actions[signal] = SignalAction::Default;
+    }
+    let _ = process::send_signal(process::get_running_process_pid(), signal);
+}
+
+// Handles the pending, unblocked signals of the running process before it
+// goes back to user mode. Ignored signals are dropped, the default action
+// terminates or stops and a handler gets a signal frame pushed on the user
+// stack.
+// Returns the trap frame to switch to.
+pub fn deliver_pending_signals(trap_frame: *mut TrapFrame) -> *mut TrapFrame {
+    let running = process::running_process_mut();
+    if running.pid == process::IDLE_ID {
+        return trap_frame;
+    }
+
+    while let Some(signal) = process::take_pending_signal(running.pid, running.signals.blocked) {
+        let action = match running.signals.actions[signal] {
+            SignalAction::Default => match default_action(signal) {
+                DefaultAction::Ignore | DefaultAction::Continue => SignalAction::Ignore,
+                DefaultAction::Stop => {
+                    if process::stop_running_process() {
+                        scheduler::schedule();
+                    }
+                    continue;
+                }
+                DefaultAction::Terminate => SignalAction::Default,
+            },
+            action => action,
+        };
+
+        match action {
+            SignalAction::Ignore => continue,
+            SignalAction::Default => terminate(signal),
+            SignalAction::Handler { handler, restorer } => {
+                debug!("delivering signal {} to pid {}", signal, running.pid);
+                match push_signal_frame(trap_frame, signal, handler, restorer) {
+                    Some(handler_frame) => {
+                        running.signals.blocked |= signal_bit(signal);
+                        process::update_running_process_trap_frame(handler_frame);
+                        return handler_frame;
+                    }
+                    // No room for the frame, nothing sensible left to do
+                    None => terminate(SIGSEGV),
+                }
+            }
+        }
+    }
+
+    trap_frame
+}
+
+fn terminate(signal: usize) -> ! {
+    println!(
+        "pid {} terminated by signal {}",
+        process::get_running_process_pid(),
+        signal
+    );
+    process::exit_running_process(SIGNAL_EXIT_BASE + signal);
+    scheduler::schedule();
+}
+
+// Saves the interrupted context right below the user stack pointer and
+// builds the context of the handler on top of it:
+//
+//   interrupted sp -> | ...                           |
+//                     | vector registers              |
+//                     | SignalFrame                   | <- handler sp, a1
+//                     | TrapFrame of the handler      |
+//
+// The handler gets a0 = signal, a1 = frame and returns to the restorer.
+fn push_signal_frame(
+    trap_frame: *mut TrapFrame,
+    signal: usize,
+    handler: usize,
+    restorer: usize,
+) -> Option<*mut TrapFrame> {
+    let running = process::running_process();
+    let vector_registers = running
+        .vector_state
+        .as_ref()
+        .map_or(&[][..], |vector_state| vector_state.registers());
+    let trap_frame_size = core::mem::size_of::<TrapFrame>();
+    let frame_size = core::mem::size_of::<SignalFrame>();
+
+    let interrupted_sp = unsafe { (*trap_frame).regs[GeneralPurposeRegister::Sp as usize] }
+        .checked_add(trap_frame_size)?;
+    let frame_address = interrupted_sp
+        .checked_sub(vector_registers.len())?
+        .checked_sub(frame_size)?
+        & !0xf;
+    let handler_frame_address = frame_address.checked_sub(trap_frame_size)?;
+
+    if !process::running_process_stack_contains(
+        handler_frame_address,
+        interrupted_sp - handler_frame_address,
+    ) {
+        return None;
+    }
+
+    let frame = frame_address as *mut SignalFrame;
+    let handler_frame = handler_frame_address as *mut TrapFrame;
+    unsafe {
+        (*frame).trap_frame = *trap_frame;
+        (*frame).blocked = running.signals.blocked;
+        (*frame).signal = signal;
+        // Saved on trap entry, the copy in memory is up to date
+        (*frame).fp_used = running.fp_state.is_some() as usize;
+        if let Some(fp_state) = running.fp_state.as_ref() {
+            for (saved, freg) in (*frame).trap_frame.fregs.iter_mut().zip(&fp_state.fregs) {
+                *saved = *freg as usize;
+            }
+            (*frame).fcsr = fp_state.fcsr;
+        }
+        (*frame).vector_size = vector_registers.len();
+        if let Some(vector_state) = running.vector_state.as_ref() {
+            let saved = (frame_address + frame_size) as *mut u8;
+            core::ptr::copy_nonoverlapping(
+                vector_registers.as_ptr(),
+                saved,
+                vector_registers.len(),
+            );
+            (*frame).vector_csrs = vector_state.csrs();
+        }
+
+        *handler_frame = *trap_frame;
+        (*handler_frame).pc = handler;
+        (*handler_frame).regs[GeneralPurposeRegister::Sp as usize] = handler_frame_address;
+        (*handler_frame).regs[GeneralPurposeRegister::Ra as usize] = restorer;
+        (*handler_frame).regs[GeneralPurposeRegister::A0 as usize] = signal;
+        (*handler_frame).regs[GeneralPurposeRegister::A1 as usize] = frame_address;
+    }
+
+    Some(handler_frame)
+}
+
+// sigreturn: the restorer calls it with sp back at the signal frame, so the
+// frame sits right above the trap frame of the ecall. Returns the context
+// to resume, None if there is no valid frame there.
+pub fn sigreturn(trap_frame: *mut TrapFrame) -> Option<*mut TrapFrame> {
+    let frame_size = core::mem::size_of::<SignalFrame>();
+    let frame_address = unsafe { (*trap_frame).regs[GeneralPurposeRegister::Sp as usize] }
+        .checked_add(core::mem::size_of::<TrapFrame>())?;
+
+    if !process::running_process_stack_contains(frame_address, frame_size) {
+        return None;
+    }
+
+    let frame = frame_address as *mut SignalFrame;
+    let running = process::running_process_mut();
+
+    // The process has a vector save area since the delivery if the frame
+    // says so, the saved registers must fill it
+    let vector_size = unsafe { (*frame).vector_size };
+    let vector_address = frame_address + frame_size;
+    let save_area_size = running
+        .vector_state
+        .as_ref()
+        .map_or(0, |vector_state| vector_state.registers().len());
+    if vector_size != 0
+        && (vector_size != save_area_size
+            || !process::running_process_stack_contains(vector_address, vector_size))
+    {
+        return None;
+    }
+
+    unsafe {
+        running.signals.blocked = (*frame).blocked & !UNBLOCKABLE;
+
+        // A handler may have used the FPU first, the interrupted context
+        // didn't care about its registers then
+        if (*frame).fp_used != 0 {
+            let mut fregs = [0; 32];
+            for (freg, saved) in fregs.iter_mut().zip(&(*frame).trap_frame.fregs) {
+                *freg = *saved as u64;
+            }
+            running
+                .fp_state
+                .get_or_insert_with(FloatingPointState::new)
+                .restore(&fregs, (*frame).fcsr);
+        }
+        if let Some(vector_state) = running.vector_state.as_mut().filter(|_| vector_size != 0) {
+            let registers = core::slice::from_raw_parts(vector_address as *const u8, vector_size);
+            vector_state.restore(registers, (*frame).vector_csrs);
+        }
+
+        // The frame lives in user memory: never trust the privileged parts
+        let restored = &mut (*frame).trap_frame;
+        restored.mode = cpu::CpuMode::User as usize;
+        restored.global_interrupt_enable = 0;
+        restored.satp = (*trap_frame).satp;
+        Some(restored as *mut TrapFrame)
+    }
+}
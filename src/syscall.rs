@@ -0,0 +1,454 @@
+// syscall.rs
+// System call dispatch
+// tongOS team
+
+// The ABI (numbers, errno and how results are encoded) is in abi.rs.
+// Every handler gets the trap frame of the ecall and its five arguments,
+// and tells the dispatcher how the caller goes on.
+
+use crate::abi::{self, Errno, SyscallResult};
+use crate::app::embedded;
+use crate::console;
+use crate::cpu::{self, GeneralPurposeRegister, TrapFrame};
+use crate::elf::ElfError;
+use crate::process;
+use crate::scheduler;
+use crate::signal;
+use crate::trap;
+use crate::user_memory;
+
+use alloc::vec::Vec;
+
+pub enum SyscallOutcome {
+    // Back to the caller with the result in a0
+    Return(SyscallResult),
+    // The caller left the CPU: it exited, or it is blocked or sleeping and
+    // its a0 is written by whoever wakes it up
+    Reschedule,
+    // Resume another context of the caller (sigreturn)
+    Resume(*mut TrapFrame),
+}
+
+type SyscallHandler = fn(*mut TrapFrame, [usize; 5]) -> SyscallOutcome;
+
+// Indexed by syscall number
+static SYSCALL_TABLE: [SyscallHandler; abi::SYSCALL_COUNT] = [
+    sys_exit,
+    sys_create_thread,
+    sys_join,
+    sys_sleep,
+    sys_read_line,
+    sys_print_str,
+    sys_time_now,
+    sys_getpid,
+    sys_getppid,
+    sys_kill,
+    sys_sigaction,
+    sys_sigreturn,
+    sys_sigprocmask,
+    sys_spawn,
+    sys_process_list,
+    sys_getrusage,
+    sys_sched_getpolicy,
+    sys_sched_setpolicy,
+    sys_nice,
+    sys_sched_setperiodic,
+    sys_sched_wait_period,
+    sys_sched_stats,
+    sys_sched_setaffinity,
+    sys_sched_getaffinity,
+];
+
+// ecall from user mode
+pub fn handle_syscall(trap_frame: *mut TrapFrame) -> ! {
+    let (number, args) = unsafe {
+        let regs = &(*trap_frame).regs;
+        (
+            regs[GeneralPurposeRegister::A0 as usize],
+            [
+                regs[GeneralPurposeRegister::A1 as usize],
+                regs[GeneralPurposeRegister::A2 as usize],
+                regs[GeneralPurposeRegister::A3 as usize],
+                regs[GeneralPurposeRegister::A4 as usize],
+                regs[GeneralPurposeRegister::A5 as usize],
+            ],
+        )
+    };
+
+    debug!(
+        "handling syscall {} from pid {}",
+        number,
+        process::get_running_process_pid()
+    );
+
+    // Whatever happens, the caller continues after the ecall
+    unsafe {
+        (*trap_frame).pc += 4;
+    }
+
+    let outcome = match SYSCALL_TABLE.get(number) {
+        Some(handler) => handler(trap_frame, args),
+        None => SyscallOutcome::Return(Err(Errno::ENOSYS)),
+    };
+
+    match outcome {
+        SyscallOutcome::Return(result) => {
+            set_return_value(trap_frame, result);
+            process::switch_to_process(trap_frame);
+        }
+        SyscallOutcome::Reschedule => scheduler::schedule(),
+        SyscallOutcome::Resume(context) => {
+            process::update_running_process_trap_frame(context);
+            process::switch_to_process(context);
+        }
+    }
+}
+
+fn set_return_value(trap_frame: *mut TrapFrame, result: SyscallResult) {
+    unsafe {
+        (*trap_frame).regs[GeneralPurposeRegister::A0 as usize] = abi::encode(result);
+    }
+}
+
+fn sys_exit(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    process::exit_running_process(args[0]);
+    SyscallOutcome::Reschedule
+}
+
+// args: start address, then three arguments for it
+fn sys_create_thread(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    if args[0] == 0 {
+        return SyscallOutcome::Return(Err(Errno::EINVAL));
+    }
+    let thread = process::Process::new_thread(
+        process::running_process(),
+        args[0],
+        args[1],
+        args[2],
+        args[3],
+    );
+    let pid = thread.pid;
+    process::child_process_list_add(thread);
+    SyscallOutcome::Return(Ok(pid))
+}
+
+fn sys_join(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    match process::join_process(args[0]) {
+        process::JoinResult::Exited(exit_code) => SyscallOutcome::Return(Ok(exit_code)),
+        process::JoinResult::NoSuchProcess => SyscallOutcome::Return(Err(Errno::ESRCH)),
+        // exit_running_process writes our a0
+        process::JoinResult::Blocked => SyscallOutcome::Reschedule,
+    }
+}
+
+// args: number of context switch periods
+fn sys_sleep(trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let until = match args[0]
+        .checked_mul(cpu::CONTEXT_SWITCH_TIME as usize)
+        .and_then(|amount| amount.checked_add(trap::get_mtime() as usize))
+    {
+        Some(until) => until,
+        None => return SyscallOutcome::Return(Err(Errno::EINVAL)),
+    };
+
+    if crate::ENABLE_PREEMPTION {
+        // Nobody writes a0 when the sleep is over
+        set_return_value(trap_frame, Ok(0));
+        process::put_process_to_sleep(until);
+        SyscallOutcome::Reschedule
+    } else {
+        while (trap::get_mtime() as usize) < until {}
+        trap::schedule_machine_timer_interrupt(1);
+        SyscallOutcome::Return(Ok(0))
+    }
+}
+
+// args: buffer, capacity. Returns the length of the line, without the
+// newline, cut at capacity.
+fn sys_read_line(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    match console::read_line(args[0], args[1]) {
+        console::ReadResult::Done(result) => SyscallOutcome::Return(result),
+        // console::handle_input writes our a0
+        console::ReadResult::Blocked => SyscallOutcome::Reschedule,
+    }
+}
+
+// args: buffer, length. Returns the length printed.
+fn sys_print_str(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let text = match user_memory::running_page_table()
+        .and_then(|page_table| user_memory::read_user_str(page_table, args[0], args[1]))
+    {
+        Ok(text) => text,
+        Err(errno) => return SyscallOutcome::Return(Err(errno)),
+    };
+
+    println!(
+        "| c hart: {}, p hart: {}, pid: {} | {}",
+        cpu::get_mhartid(),
+        process::running_process().previous_hart,
+        process::get_running_process_pid(),
+        text
+    );
+    SyscallOutcome::Return(Ok(text.len()))
+}
+
+fn sys_time_now(_trap_frame: *mut TrapFrame, _args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(Ok(trap::get_mtime() as usize))
+}
+
+fn sys_getpid(_trap_frame: *mut TrapFrame, _args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(Ok(process::get_running_process_pid()))
+}
+
+fn sys_getppid(_trap_frame: *mut TrapFrame, _args: [usize; 5]) -> SyscallOutcome {
+    let parent = process::get_parent_pid(process::get_running_process_pid());
+    SyscallOutcome::Return(parent.ok_or(Errno::ESRCH))
+}
+
+// args: pid, signal. The caller may signal itself, the signal is delivered
+// on the way back.
+fn sys_kill(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(process::send_signal(args[0], args[1]).map(|_| 0))
+}
+
+// args: signal, handler (or SIG_DFL, SIG_IGN), restorer
+fn sys_sigaction(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let action = match args[1] {
+        signal::SIG_DFL => signal::SignalAction::Default,
+        signal::SIG_IGN => signal::SignalAction::Ignore,
+        handler => signal::SignalAction::Handler {
+            handler,
+            restorer: args[2],
+        },
+    };
+    let signals = &mut process::running_process_mut().signals;
+    if signals.set_action(args[0], action) {
+        SyscallOutcome::Return(Ok(0))
+    } else {
+        SyscallOutcome::Return(Err(Errno::EINVAL))
+    }
+}
+
+fn sys_sigreturn(trap_frame: *mut TrapFrame, _args: [usize; 5]) -> SyscallOutcome {
+    match signal::sigreturn(trap_frame) {
+        Some(context) => SyscallOutcome::Resume(context),
+        None => {
+            signal::force_signal(signal::SIGSEGV);
+            SyscallOutcome::Return(Err(Errno::EFAULT))
+        }
+    }
+}
+
+// args: how, mask. Returns the previous mask. Unblocking may leave signals
+// ready to deliver on the way back.
+fn sys_sigprocmask(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let signals = &mut process::running_process_mut().signals;
+    let old = signals.change_blocked(args[0], args[1] as u64);
+    SyscallOutcome::Return(old.map(|old| old as usize).ok_or(Errno::EINVAL))
+}
+
+// args: name, name length, argv, envp. argv and envp are NULL terminated
+// arrays of NUL terminated strings, both limited together by abi::MAX_ARGS
+// and abi::MAX_ARGS_SIZE. Starts the embedded program called name as a
+// child of the caller and returns its pid.
+fn sys_spawn(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(spawn(args))
+}
+
+fn spawn(args: [usize; 5]) -> SyscallResult {
+    let page_table = user_memory::running_page_table()?;
+    let name = user_memory::read_user_str(page_table, args[0], args[1])?;
+    let argv =
+        user_memory::read_user_str_array(page_table, args[2], abi::MAX_ARGS, abi::MAX_ARGS_SIZE)?;
+    let argv_size: usize = argv.iter().map(|argument| argument.len() + 1).sum();
+    let envp = user_memory::read_user_str_array(
+        page_table,
+        args[3],
+        abi::MAX_ARGS - argv.len(),
+        abi::MAX_ARGS_SIZE - argv_size,
+    )?;
+
+    let image = embedded::find(&name).ok_or(Errno::ENOENT)?;
+    let argv: Vec<&str> = argv.iter().map(|argument| argument.as_str()).collect();
+    let envp: Vec<&str> = envp.iter().map(|variable| variable.as_str()).collect();
+    let mut child = process::Process::new_from_elf(&name, image, &argv, &envp).map_err(
+        |error| match error {
+            ElfError::OutOfMemory => Errno::ENOMEM,
+            _ => Errno::ENOEXEC,
+        },
+    )?;
+
+    child.set_nice(process::running_process().nice);
+    child.affinity = process::running_process().affinity;
+    let pid = child.pid;
+    process::child_process_list_add(child);
+    Ok(pid)
+}
+
+// args: buffer, capacity. Fills buffer with up to capacity abi::ProcessInfo
+// entries, ordered by pid, and returns how many processes there are, which
+// may be more than capacity.
+fn sys_process_list(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(process_list(args))
+}
+
+fn process_list(args: [usize; 5]) -> SyscallResult {
+    let page_table = user_memory::running_page_table()?;
+    let snapshot = process::process_list_snapshot();
+    let count = snapshot.len().min(args[1]);
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            snapshot.as_ptr() as *const u8,
+            count * core::mem::size_of::<abi::ProcessInfo>(),
+        )
+    };
+    user_memory::copy_to_user(page_table, args[0], bytes)?;
+    Ok(snapshot.len())
+}
+
+// args: pid (0 for the caller), buffer. Writes the abi::ResourceUsage of
+// pid to buffer.
+fn sys_getrusage(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(getrusage(args))
+}
+
+fn getrusage(args: [usize; 5]) -> SyscallResult {
+    let page_table = user_memory::running_page_table()?;
+    let usage = process::get_resource_usage(args[0]).ok_or(Errno::ESRCH)?;
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &usage as *const abi::ResourceUsage as *const u8,
+            core::mem::size_of::<abi::ResourceUsage>(),
+        )
+    };
+    user_memory::copy_to_user(page_table, args[1], bytes)?;
+    Ok(0)
+}
+
+// Returns the scheduling policy, one of abi::SCHED_*
+fn sys_sched_getpolicy(_trap_frame: *mut TrapFrame, _args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(Ok(scheduler::get_policy()))
+}
+
+// args: policy. Switches every hart to it and returns the previous one.
+fn sys_sched_setpolicy(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(scheduler::set_policy(args[0]))
+}
+
+// args: pid (0 for the caller), nice. Sets the static priority of pid,
+// between abi::NICE_MIN and abi::NICE_MAX. EPERM if the caller lowers the
+// nice of a process it is not an ancestor of.
+fn sys_nice(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let nice = args[1] as isize;
+    if nice < abi::NICE_MIN || nice > abi::NICE_MAX {
+        return SyscallOutcome::Return(Err(Errno::EINVAL));
+    }
+    SyscallOutcome::Return(process::set_process_nice(args[0], nice).map(|_| 0))
+}
+
+// args: period, wcet, deadline, in context switch periods. A deadline of 0
+// is the period, a period of 0 makes the caller a normal process again.
+// Makes the caller a periodic real-time task, its first job released now,
+// and returns the hart that admitted it. EBUSY if no hart can meet its
+// deadlines.
+fn sys_sched_setperiodic(trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let to_ticks = |periods: usize| periods.checked_mul(cpu::CONTEXT_SWITCH_TIME as usize);
+    let deadline = if args[2] == 0 { args[0] } else { args[2] };
+    let times = (to_ticks(args[0]), to_ticks(args[1]), to_ticks(deadline));
+
+    let hart = process::with_running_attributes(|running| {
+        if args[0] == 0 {
+            if let Some(realtime) = running.realtime.take() {
+                scheduler::edf::release(&realtime);
+            }
+            return Ok(None);
+        }
+        let realtime = match times {
+            (Some(period), Some(wcet), Some(deadline)) => scheduler::edf::admit(
+                period,
+                wcet,
+                deadline,
+                running.affinity,
+                running.realtime.as_ref(),
+            ),
+            _ => Err(Errno::EINVAL),
+        }?;
+        running.realtime = Some(realtime);
+        Ok(Some(realtime.hart))
+    });
+    match hart {
+        Ok(None) => SyscallOutcome::Return(Ok(0)),
+        Ok(Some(hart)) => {
+            // Off to the hart that admitted it
+            set_return_value(trap_frame, Ok(hart));
+            process::yield_running_process();
+            SyscallOutcome::Reschedule
+        }
+        Err(errno) => SyscallOutcome::Return(Err(errno)),
+    }
+}
+
+// Ends the current job of a periodic task and waits for the release of the
+// next one
+fn sys_sched_wait_period(trap_frame: *mut TrapFrame, _args: [usize; 5]) -> SyscallOutcome {
+    let running = process::running_process_mut();
+    if running.realtime.is_none() {
+        return SyscallOutcome::Return(Err(Errno::EINVAL));
+    }
+    let release = scheduler::edf::complete_job(running);
+
+    set_return_value(trap_frame, Ok(0));
+    if release > trap::get_mtime() as usize {
+        process::put_process_to_sleep(release);
+    } else {
+        // Late, the next job is due already
+        process::yield_running_process();
+    }
+    SyscallOutcome::Reschedule
+}
+
+// args: buffer, capacity. Fills buffer with up to capacity abi::HartStats,
+// one per hart, and returns the number of harts.
+fn sys_sched_stats(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    SyscallOutcome::Return(sched_stats(args))
+}
+
+fn sched_stats(args: [usize; 5]) -> SyscallResult {
+    let page_table = user_memory::running_page_table()?;
+    let stats = scheduler::hart_stats();
+    let count = stats.len().min(args[1]);
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            stats.as_ptr() as *const u8,
+            count * core::mem::size_of::<abi::HartStats>(),
+        )
+    };
+    user_memory::copy_to_user(page_table, args[0], bytes)?;
+    Ok(stats.len())
+}
+
+// args: pid (0 for the caller), mask of abi::AFFINITY_ALL bits. Sets the
+// harts pid may run on. EINVAL if the mask has none, or leaves out the hart
+// of a real-time task. A caller that may not stay on its hart moves now.
+fn sys_sched_setaffinity(trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let affinity = args[1] as u64 & abi::AFFINITY_ALL;
+    if affinity == 0 {
+        return SyscallOutcome::Return(Err(Errno::EINVAL));
+    }
+    if let Err(errno) = process::set_process_affinity(args[0], affinity) {
+        return SyscallOutcome::Return(Err(errno));
+    }
+
+    if scheduler::allowed(process::running_process(), cpu::get_mhartid()) {
+        return SyscallOutcome::Return(Ok(0));
+    }
+    set_return_value(trap_frame, Ok(0));
+    process::yield_running_process();
+    SyscallOutcome::Reschedule
+}
+
+// args: pid, 0 for the caller. Returns the harts pid may run on.
+fn sys_sched_getaffinity(_trap_frame: *mut TrapFrame, args: [usize; 5]) -> SyscallOutcome {
+    let affinity = process::get_process_affinity(args[0]).ok_or(Errno::ESRCH);
+    SyscallOutcome::Return(affinity.map(|affinity| affinity as usize))
+}
@@ -0,0 +1,92 @@
+// tls.rs
+// Thread-local storage of user threads
+// tongOS team
+
+// RISC-V uses TLS variant I with an empty TCB: tp points right at the
+// thread's copy of the TLS segment, initialized data (.tdata) followed by
+// zeroes (.tbss). Every thread gets its copy at the top of its stack.
+// The kernel runs with the tp of the interrupted thread, so kernel code
+// must never use #[thread_local].
+
+use crate::assembly;
+use crate::page;
+
+use alloc::vec::Vec;
+
+// Carved from the user stack, keep it small
+pub const MAX_TLS_SIZE: usize = 2 * page::PAGE_SIZE;
+
+// Alignment of the kernel image template, see qemu_virt.lds
+const KERNEL_TLS_ALIGN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct TlsTemplate {
+    initial: Vec<u8>,
+    memory_size: usize,
+    align: usize,
+    // Link address of the template, the linker computes the offsets from tp
+    // with it
+    address: usize,
+}
+
+impl TlsTemplate {
+    // None if it doesn't fit in MAX_TLS_SIZE. align is p_align: 0, 1 or a
+    // power of two.
+    pub fn new(initial: &[u8], memory_size: usize, align: usize, address: usize) -> Option<Self> {
+        if align > MAX_TLS_SIZE || (align > 1 && !align.is_power_of_two()) {
+            return None;
+        }
+        let align = align.max(16);
+        let size = memory_size.checked_add(align)?;
+        if initial.len() > memory_size || size > MAX_TLS_SIZE {
+            return None;
+        }
+        Some(TlsTemplate {
+            initial: initial.to_vec(),
+            memory_size,
+            align,
+            address,
+        })
+    }
+
+    // .tdata and .tbss of the kernel image, shared by the apps linked into
+    // it. None if they are empty.
+    pub fn kernel_image() -> Option<Self> {
+        let (start, data_end, end) = unsafe {
+            (
+                assembly::TDATA_START,
+                assembly::TDATA_END,
+                assembly::TBSS_END,
+            )
+        };
+        if end <= start {
+            return None;
+        }
+        let initial = unsafe { core::slice::from_raw_parts(start as *const u8, data_end - start) };
+        let template = TlsTemplate::new(initial, end - start, KERNEL_TLS_ALIGN, start);
+        assert!(template.is_some(), "kernel TLS template too large");
+        template
+    }
+
+    // Copies the template right below stack_end, which must be identity
+    // mapped. Returns the thread pointer and the new stack end.
+    pub fn push(&self, stack_end: usize) -> (usize, usize) {
+        let block = (stack_end - self.memory_size) & !(self.align - 1);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.initial.as_ptr(),
+                block as *mut u8,
+                self.initial.len(),
+            );
+            core::ptr::write_bytes(
+                (block + self.initial.len()) as *mut u8,
+                0,
+                self.memory_size - self.initial.len(),
+            );
+        }
+
+        // Offsets from tp include the misalignment of the template address
+        let thread_pointer = block - (self.address & (self.align - 1));
+        (thread_pointer, block & !0xf)
+    }
+}
@@ -3,10 +3,14 @@
 // Stephen Marz
 // tongOS team
 
-use crate::cpu::{self, GeneralPurposeRegister, TrapFrame};
+use crate::address_space;
+use crate::console;
+use crate::cpu::{self, TrapFrame};
 use crate::plic;
 use crate::process;
 use crate::scheduler;
+use crate::signal;
+use crate::syscall;
 use crate::uart;
 
 pub fn init() {
@@ -16,7 +20,12 @@ pub fn init() {
 
     // [7] = MTIE (Machine Time Interrupt Enable)
     // [3] = MSIE (Machine Software Interrupt Enable)
-    let flags = 1 << 7 | 1 << 3;
+    let mut flags = 1 << 7 | 1 << 3;
+    // [11] = MEIE (Machine External Interrupt Enable), the PLIC only
+    // interrupts hart 0, see console.rs
+    if cpu::get_mhartid() == 0 {
+        flags |= 1 << 11;
+    }
     unsafe { asm!("csrw mie, {}", in(reg) flags) }
 }
 
@@ -99,6 +108,9 @@ pub fn schedule_machine_timer_interrupt(quantum: usize) {
 #[no_mangle]
 pub fn tong_os_trap(trap_frame: *mut TrapFrame) {
     process::update_running_process_trap_frame(trap_frame);
+    process::account_trap_entry();
+    process::apply_pending_attributes();
+    process::save_running_process_extension_state();
     unsafe {
         debug!(
             "trap: mcause: {:x}, MIE {}, MPIE {}, pid {}, global_interrupt_enable {}, mode: {:?}, ",
@@ -121,19 +133,29 @@ pub fn tong_os_trap(trap_frame: *mut TrapFrame) {
         match cause {
             3 => {
                 complete_software_interrupt(cpu::get_mhartid());
+                address_space::flush_tlb_if_requested();
                 debug!(
                     "Handling asyng software interrupt on hart {}",
                     cpu::get_mhartid()
                 );
 
-                assert!(
-                    process::get_running_process_pid() == process::IDLE_ID,
-                    "found pid : {}",
-                    process::get_running_process_pid()
-                );
-
-                process::yield_idle_process();
-                scheduler::schedule();
+                // An idle hart got a process to run, or a real-time job
+                // that takes the hart was released here. A running process
+                // may also have been sent a signal from another hart, it is
+                // delivered on the way back, or given an affinity that
+                // leaves this hart out.
+                if process::get_running_process_pid() == process::IDLE_ID {
+                    process::yield_idle_process();
+                    scheduler::schedule();
+                }
+                let hartid = cpu::get_mhartid();
+                if scheduler::preempts_running()
+                    || !scheduler::allowed(process::running_process(), hartid)
+                {
+                    process::yield_running_process();
+                    scheduler::schedule();
+                }
+                process::switch_to_process(trap_frame);
             }
             7 => {
                 debug!(
@@ -146,60 +168,35 @@ pub fn tong_os_trap(trap_frame: *mut TrapFrame) {
                 let has_awaken = process::try_wake_sleeping();
 
                 if process::get_running_process_pid() == process::IDLE_ID {
-                    if has_awaken {
+                    scheduler::sample_load();
+                    if has_awaken || scheduler::steal_work() {
                         process::yield_idle_process();
                         scheduler::schedule();
                     }
                     schedule_machine_timer_interrupt(1);
                     process::switch_to_process(trap_frame);
                 } else {
-                    process::yield_running_process();
-                    scheduler::schedule();
+                    if scheduler::tick(process::running_process_mut()) {
+                        process::yield_running_process();
+                        scheduler::schedule();
+                    }
+                    schedule_machine_timer_interrupt(
+                        scheduler::quantum(process::running_process()),
+                    );
+                    process::switch_to_process(trap_frame);
                 }
             }
-            11 => unsafe {
+            11 => {
                 debug!("Handling external interrupt!");
 
-                let buffer: &mut alloc::string::String =
-                    core::mem::transmute((*trap_frame).regs[GeneralPurposeRegister::A1 as usize]);
-
+                let mut has_awaken = false;
                 if let Some(external_interrupt) = plic::next() {
                     match external_interrupt {
                         // UART
                         10 => {
                             let mut uart = uart::Uart::new(0x1000_0000);
-
                             if let Some(c) = uart.get() {
-                                match c {
-                                    // backspace
-                                    8 | 127 => {
-                                        // remove last char from buffer
-                                        print!("{0} {0}", 8 as char);
-                                        buffer.pop();
-                                        plic::complete(external_interrupt);
-                                        process::switch_to_process(trap_frame);
-                                    }
-                                    // Enter
-                                    10 | 13 => {
-                                        println!("");
-                                        uart::READING = false;
-                                        plic::complete(external_interrupt);
-
-                                        let flags = 1 << 7;
-                                        asm!("csrw mie, {}", in(reg) flags);
-
-                                        schedule_machine_timer_interrupt(1);
-                                        process::switch_to_process(trap_frame);
-                                    }
-                                    // Char
-                                    _ => {
-                                        print!("{}", c as char);
-                                        buffer.push(c as char);
-
-                                        plic::complete(external_interrupt);
-                                        process::switch_to_process(trap_frame);
-                                    }
-                                }
+                                has_awaken = console::handle_input(c);
                             }
                         }
                         other => panic!(
@@ -207,8 +204,15 @@ pub fn tong_os_trap(trap_frame: *mut TrapFrame) {
                             cause, other
                         ),
                     }
+                    plic::complete(external_interrupt);
                 }
-            },
+
+                if has_awaken && process::get_running_process_pid() == process::IDLE_ID {
+                    process::yield_idle_process();
+                    scheduler::schedule();
+                }
+                process::switch_to_process(trap_frame);
+            }
             _ => {
                 panic!(
                     "Unhandled async trap CPU#{} -> {}\n",
@@ -219,197 +223,71 @@ pub fn tong_os_trap(trap_frame: *mut TrapFrame) {
         }
     } else {
         match cause {
-            8 => {
-                let which_code = unsafe { (*trap_frame).regs[GeneralPurposeRegister::A0 as usize] };
-
-                debug!(
-                    "Handling user ecall exception: mcause {}, pid {}, syscall code {}",
-                    cause,
-                    process::get_running_process_pid(),
-                    which_code,
-                );
-
-                match which_code {
-                    // Exiting process
-                    0 => {
-                        debug!("handling exit");
-                        // Check if child process needs to reschedule parent
-                        if let Some(blocked) = process::get_running_process_blocking_pid() {
-                            debug!("waking blocked: {}", blocked);
-                            process::unblock_process_by_pid(blocked);
-                            // wake_all_idle_harts();
-                        }
-                        process::delete_running_process();
-                        scheduler::schedule();
-                    }
-                    // Create thread
-                    1 => {
-                        debug!("handling create thread");
-                        let process_address =
-                            unsafe { (*trap_frame).regs[GeneralPurposeRegister::A1 as usize] };
-                        let process_arg0 =
-                            unsafe { (*trap_frame).regs[GeneralPurposeRegister::A2 as usize] };
-                        let process_arg1 =
-                            unsafe { (*trap_frame).regs[GeneralPurposeRegister::A3 as usize] };
-                        let process_arg2 =
-                            unsafe { (*trap_frame).regs[GeneralPurposeRegister::A4 as usize] };
-                        let new_process = process::Process::new(
-                            process_address,
-                            process_arg0,
-                            process_arg1,
-                            process_arg2,
-                        );
-                        let new_process_pid = new_process.pid;
-                        process::process_list_add(new_process);
-                        unsafe {
-                            (*trap_frame).regs[GeneralPurposeRegister::A0 as usize] =
-                                new_process_pid;
-                            (*trap_frame).pc += 4;
-                        }
-                        // wake_all_idle_harts();
-                        process::switch_to_process(trap_frame);
-                    }
-                    // Joining thread
-                    2 => {
-                        // unsafe {
-                        //     crate::DEBUG_OUTPUT = true;
-                        // }
-                        debug!("handling join");
-                        let joining_pid =
-                            unsafe { (*trap_frame).regs[GeneralPurposeRegister::A1 as usize] };
-                        debug!("joining pid: {}", joining_pid);
-
-                        unsafe {
-                            (*trap_frame).pc += 4;
-                        };
-                        // if joining pid has already exited
-                        if !process::pid_list_contains(joining_pid) {
-                            debug!("not contains");
-                            unsafe {
-                                crate::DEBUG_OUTPUT = false;
-                            }
-                            process::switch_to_process(trap_frame);
-                        } else {
-                            debug!("contains");
-                            let blocking_pid = process::get_running_process_pid();
-                            process::set_blocking_pid(joining_pid, blocking_pid);
-                            debug!("calling schedule()");
-                            // unsafe {
-                            //     crate::DEBUG_OUTPUT = false;
-                            // }
-                            process::block_process();
-                            scheduler::schedule();
-                        }
-                    }
-                    // syscall sleep
-                    3 => {
-                        debug!("handling sleep");
-                        unsafe {
-                            (*trap_frame).pc += 4;
-                        };
-                        let amount =
-                            unsafe { (*trap_frame).regs[GeneralPurposeRegister::A1 as usize] };
-                        let until =
-                            get_mtime() as usize + amount * cpu::CONTEXT_SWITCH_TIME as usize;
-
-                        if crate::ENABLE_PREEMPTION {
-                            process::put_process_to_sleep(until);
-                            scheduler::schedule();
-                        } else {
-                            // sleep
-                            loop {
-                                if (get_mtime() as usize) >= until {
-                                    break;
-                                }
-                            }
-                            schedule_machine_timer_interrupt(1);
-                            process::switch_to_process(trap_frame);
-                        }
-                    }
-                    // syscall input keyboard
-                    4 => {
-                        debug!("handling input keyboard");
-                        unsafe {
-                            uart::READING = true;
-                        }
-                        // UART
-                        plic::set_threshold(6);
-                        plic::set_priority(10, 7);
-                        plic::enable(10);
-
-                        unsafe {
-                            // [11] = MEIE (Machine External Interrupt Enable)
-                            let flags = 1 << 11;
-                            asm!("csrw mie, {}", in(reg) flags);
-                        }
-                        unsafe {
-                            (*trap_frame).pc += 4;
-                        }
-                        process::switch_to_process(trap_frame);
-                    }
-                    // syscall print str
-                    5 => {
-                        debug!("handling print str");
-                        unsafe {
-                            (*trap_frame).pc += 4;
-                        }
-                        let buffer: *const u8 = unsafe {
-                            core::mem::transmute(
-                                (*trap_frame).regs[GeneralPurposeRegister::A1 as usize],
-                            )
-                        };
-
-                        let len: usize = unsafe {
-                            core::mem::transmute(
-                                (*trap_frame).regs[GeneralPurposeRegister::A2 as usize],
-                            )
-                        };
-
-                        let slice = unsafe {
-                            let slice = core::slice::from_raw_parts(buffer, len);
-                            core::str::from_utf8_unchecked(slice)
-                        };
-
-                        println!(
-                            "| c hart: {}, p hart: {}, pid: {} | {}",
-                            cpu::get_mhartid(),
-                            process::running_list()[cpu::get_mhartid()]
-                                .as_ref()
-                                .unwrap()
-                                .previous_hart,
-                            process::get_running_process_pid(),
-                            slice
-                        );
-                        process::switch_to_process(trap_frame);
-                    }
-                    // get time
-                    6 => {
-                        debug!("handling print str");
-                        unsafe {
-                            (*trap_frame).regs[GeneralPurposeRegister::A0 as usize] =
-                                get_mtime() as usize;
-                            (*trap_frame).pc += 4;
-                        }
-
-                        process::switch_to_process(trap_frame);
-                    }
-                    code => {
-                        panic!("Unhandled user ecall with code {}", code);
-                    }
-                }
-            }
+            // ecall from user mode
+            8 => syscall::handle_syscall(trap_frame),
             cause => {
                 let mtval: usize;
                 unsafe {
                     asm!("csrr {}, mtval", out(reg) mtval);
                 }
+
+                // A faulting user process only takes itself down (or runs its
+                // handler), the kernel only panics on its own faults
+                if unsafe { (*trap_frame).mode } == cpu::CpuMode::User as usize {
+                    // First floating point or vector instruction: retry it
+                    // with the unit on
+                    if cause == 2
+                        && (process::enable_running_process_fp()
+                            || process::enable_running_process_vector())
+                    {
+                        process::switch_to_process(trap_frame);
+                    }
+
+                    report_user_fault(trap_frame, cause, mtval);
+                    signal::force_signal(signal::fault_signal(cause));
+                    process::switch_to_process(trap_frame);
+                }
+
                 panic!(
-                    "Unhandled sync trap CPU#{} -> cause: {}; mval: {:x?}\n",
+                    "Unhandled sync trap CPU#{} -> cause: {} ({}); mval: {:x?}; pc: {:x?}\n",
                     cpu::get_mhartid(),
                     cause,
-                    mtval
+                    exception_name(cause),
+                    mtval,
+                    unsafe { (*trap_frame).pc }
                 );
             }
         }
     }
 }
+
+// Synchronous exception causes (mcause with the interrupt bit clear)
+fn exception_name(cause: usize) -> &'static str {
+    match cause {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store/AMO address misaligned",
+        7 => "store/AMO access fault",
+        8 => "environment call from user mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store/AMO page fault",
+        _ => "unknown exception",
+    }
+}
+
+fn report_user_fault(trap_frame: *const TrapFrame, cause: usize, mtval: usize) {
+    println!(
+        "user fault on hart {}: pid {}, cause {} ({}), mtval {:#x}, pc {:#x}",
+        cpu::get_mhartid(),
+        process::get_running_process_pid(),
+        cause,
+        exception_name(cause),
+        mtval,
+        unsafe { (*trap_frame).pc }
+    );
+}
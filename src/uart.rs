@@ -7,8 +7,6 @@ use core::{
     fmt::{Error, Write},
 };
 
-pub static mut READING: bool = false;
-
 pub struct Uart {
     base_address: usize,
 }
@@ -0,0 +1,197 @@
+// user_memory.rs
+// Safe access to user memory from syscalls
+// tongOS team
+
+// Syscalls get plain addresses from user mode. Before the kernel touches a
+// buffer, every page of it is looked up in the process page table and must
+// be mapped, user accessible and readable (or writable). Copies go through
+// the translated physical addresses, so they work whether the process is
+// identity mapped or not.
+
+use crate::abi::Errno;
+use crate::page::{self, Sv39PageTable};
+use crate::process;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+// Largest buffer a single syscall can pass, larger ones get EINVAL
+pub const MAX_COPY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+pub fn running_page_table() -> Result<&'static Sv39PageTable, Errno> {
+    process::running_process().page_table().ok_or(Errno::EFAULT)
+}
+
+// Physical address of a user address, if the process may access it
+fn translate(page_table: &Sv39PageTable, address: usize, access: Access) -> Result<usize, Errno> {
+    if address >= page::USER_ADDRESS_LIMIT {
+        return Err(Errno::EFAULT);
+    }
+    let (physical_address, entry) = page_table.leaf_translation(address).ok_or(Errno::EFAULT)?;
+
+    let allowed = entry.is_user()
+        && match access {
+            Access::Read => entry.is_readable(),
+            Access::Write => entry.is_writable(),
+        };
+    if allowed {
+        Ok(physical_address)
+    } else {
+        Err(Errno::EFAULT)
+    }
+}
+
+// Calls f(physical address, offset in the buffer, length) for the part of
+// [address, address + len) in each page. Checks the whole range first, so
+// nothing is copied when any page is bad.
+fn for_each_page<F: FnMut(usize, usize, usize)>(
+    page_table: &Sv39PageTable,
+    address: usize,
+    len: usize,
+    access: Access,
+    mut f: F,
+) -> Result<(), Errno> {
+    if len > MAX_COPY_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let end = address.checked_add(len).ok_or(Errno::EFAULT)?;
+
+    check_user_range(page_table, address, len, access)?;
+
+    let mut current = address;
+    while current < end {
+        let page_end = page::align_address_down(current, page::PAGE_ORDER) + page::PAGE_SIZE;
+        let chunk_end = page_end.min(end);
+        let physical_address = translate(page_table, current, access)?;
+        f(physical_address, current - address, chunk_end - current);
+        current = chunk_end;
+    }
+    Ok(())
+}
+
+pub fn check_user_range(
+    page_table: &Sv39PageTable,
+    address: usize,
+    len: usize,
+    access: Access,
+) -> Result<(), Errno> {
+    let end = address.checked_add(len).ok_or(Errno::EFAULT)?;
+    let mut page_address = page::align_address_down(address, page::PAGE_ORDER);
+    while page_address < end {
+        translate(page_table, page_address, access)?;
+        page_address += page::PAGE_SIZE;
+    }
+    Ok(())
+}
+
+pub fn copy_from_user(
+    page_table: &Sv39PageTable,
+    address: usize,
+    len: usize,
+) -> Result<Vec<u8>, Errno> {
+    // Grows page by page, len is only checked by for_each_page
+    let mut bytes = Vec::new();
+    for_each_page(
+        page_table,
+        address,
+        len,
+        Access::Read,
+        |physical, _, len| {
+            let chunk = unsafe { core::slice::from_raw_parts(physical as *const u8, len) };
+            bytes.extend_from_slice(chunk);
+        },
+    )?;
+    Ok(bytes)
+}
+
+pub fn copy_to_user(page_table: &Sv39PageTable, address: usize, bytes: &[u8]) -> Result<(), Errno> {
+    for_each_page(
+        page_table,
+        address,
+        bytes.len(),
+        Access::Write,
+        |physical, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(bytes[offset..].as_ptr(), physical as *mut u8, len);
+        },
+    )
+}
+
+// A string passed as pointer and length, EINVAL if it isn't UTF-8
+pub fn read_user_str(
+    page_table: &Sv39PageTable,
+    address: usize,
+    len: usize,
+) -> Result<String, Errno> {
+    let bytes = copy_from_user(page_table, address, len)?;
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+// A NUL terminated string of at most max_len bytes before the NUL, E2BIG
+// if it is longer
+pub fn read_user_cstr(
+    page_table: &Sv39PageTable,
+    address: usize,
+    max_len: usize,
+) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut current = address;
+    loop {
+        // Up to the end of the page, a string may end right before an
+        // unmapped one
+        let page_end = page::align_address_down(current, page::PAGE_ORDER) + page::PAGE_SIZE;
+        let len = (page_end - current).min(max_len + 1 - bytes.len());
+        let chunk = copy_from_user(page_table, current, len)?;
+        match chunk.iter().position(|byte| *byte == 0) {
+            Some(nul) => {
+                bytes.extend_from_slice(&chunk[..nul]);
+                break;
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+        if bytes.len() > max_len {
+            return Err(Errno::E2BIG);
+        }
+        current += len;
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+// A NULL terminated array of string pointers, like argv. A NULL array is
+// empty. At most max_count strings taking max_size bytes with their NULs.
+pub fn read_user_str_array(
+    page_table: &Sv39PageTable,
+    address: usize,
+    max_count: usize,
+    max_size: usize,
+) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if address == 0 {
+        return Ok(strings);
+    }
+
+    let word = core::mem::size_of::<usize>();
+    let mut size = 0;
+    loop {
+        let entry = address
+            .checked_add(strings.len() * word)
+            .ok_or(Errno::EFAULT)?;
+        let pointer = copy_from_user(page_table, entry, word)?;
+        let pointer = usize::from_le_bytes(pointer[..].try_into().unwrap());
+        if pointer == 0 {
+            return Ok(strings);
+        }
+        if strings.len() == max_count || size >= max_size {
+            return Err(Errno::E2BIG);
+        }
+        let string = read_user_cstr(page_table, pointer, max_size - size - 1)?;
+        size += string.len() + 1;
+        strings.push(string);
+    }
+}
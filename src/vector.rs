@@ -0,0 +1,147 @@
+// vector.rs
+// Vector (RVV) context of user processes
+// tongOS team
+
+// Same scheme as fpu.rs, with mstatus.VS: processes start with the vector
+// unit off, the first vector instruction traps as illegal and allocates a
+// save area sized from vlenb. Registers are saved on trap entry when VS is
+// Dirty and loaded back on switch when this hart doesn't hold them already.
+// QEMU only has the extension with `-cpu rv64,v=true`, without it vector
+// instructions stay illegal.
+
+use crate::assembly;
+use crate::cpu;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MSTATUS_VS_SHIFT: usize = 9;
+const MSTATUS_VS_MASK: usize = 0b11 << MSTATUS_VS_SHIFT;
+
+const MISA_V: usize = 1 << ('V' as usize - 'A' as usize);
+
+// vtype.vill, what a hart starts with before any vsetvl
+const VTYPE_ILLEGAL: usize = 1 << 63;
+
+const NO_HART: usize = core::usize::MAX;
+
+// mstatus.VS, encoded like FS
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorStatus {
+    Off = 0,
+    Initial = 1,
+    Clean = 2,
+    Dirty = 3,
+}
+
+// Pid whose registers are loaded on each hart
+static mut VECTOR_OWNER: [usize; cpu::MAX_HARTS] = [NO_HART; cpu::MAX_HARTS];
+
+#[derive(Debug, Clone)]
+pub struct VectorState {
+    // v0-v31, vlenb bytes each
+    registers: Vec<u8>,
+    // vstart, vl, vtype, vcsr
+    csrs: [usize; 4],
+    // Hart whose registers last held this state
+    loaded_on: usize,
+}
+
+impl VectorState {
+    // The vector unit must be on to read vlenb
+    fn new() -> Self {
+        VectorState {
+            registers: vec![0; 32 * vlenb()],
+            csrs: [0, 0, VTYPE_ILLEGAL, 0],
+            loaded_on: NO_HART,
+        }
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub fn csrs(&self) -> [usize; 4] {
+        self.csrs
+    }
+
+    // sigreturn: the registers are loaded again on the next switch
+    pub fn restore(&mut self, registers: &[u8], csrs: [usize; 4]) {
+        self.registers.copy_from_slice(registers);
+        self.csrs = csrs;
+        self.loaded_on = NO_HART;
+    }
+}
+
+pub fn is_supported() -> bool {
+    cpu::get_misa() & MISA_V != 0
+}
+
+fn vlenb() -> usize {
+    let vlenb: usize;
+    unsafe { asm!("csrr {}, 0xc22", out(reg) vlenb) }
+    vlenb
+}
+
+pub fn status() -> VectorStatus {
+    match (cpu::get_mstatus() & MSTATUS_VS_MASK) >> MSTATUS_VS_SHIFT {
+        0 => VectorStatus::Off,
+        1 => VectorStatus::Initial,
+        2 => VectorStatus::Clean,
+        _ => VectorStatus::Dirty,
+    }
+}
+
+pub fn set_status(status: VectorStatus) {
+    let mstatus = cpu::get_mstatus() & !MSTATUS_VS_MASK;
+    let mstatus = mstatus | (status as usize) << MSTATUS_VS_SHIFT;
+    unsafe { asm!("csrw mstatus, {}", in(reg) mstatus) }
+}
+
+pub fn save_if_dirty(pid: usize, state: Option<&mut VectorState>) {
+    if status() != VectorStatus::Dirty {
+        return;
+    }
+    if let Some(state) = state {
+        unsafe {
+            assembly::__tong_os_vector_save(state.registers.as_mut_ptr(), state.csrs.as_mut_ptr());
+            VECTOR_OWNER[cpu::get_mhartid()] = pid;
+        }
+        state.loaded_on = cpu::get_mhartid();
+    }
+    set_status(VectorStatus::Clean);
+}
+
+pub fn switch_to(pid: usize, state: Option<&mut VectorState>) {
+    let hartid = cpu::get_mhartid();
+    match state {
+        Some(state) => {
+            set_status(VectorStatus::Clean);
+            let owner = unsafe { VECTOR_OWNER[hartid] };
+            if owner != pid || state.loaded_on != hartid {
+                unsafe {
+                    assembly::__tong_os_vector_restore(
+                        state.registers.as_ptr(),
+                        state.csrs.as_ptr(),
+                    );
+                    VECTOR_OWNER[hartid] = pid;
+                }
+                state.loaded_on = hartid;
+                set_status(VectorStatus::Clean);
+            }
+        }
+        // Harts without the extension have VS hardwired to Off
+        None => set_status(VectorStatus::Off),
+    }
+}
+
+// Illegal instruction trap, see fpu::enable_on_first_use
+pub fn enable_on_first_use(state: &mut Option<VectorState>) -> bool {
+    if state.is_some() || !is_supported() || status() != VectorStatus::Off {
+        return false;
+    }
+    set_status(VectorStatus::Initial);
+    *state = Some(VectorState::new());
+    true
+}
@@ -0,0 +1,12 @@
+// build.rs
+// Links user programs at their own address, see src/app/elf/user.lds
+// tongOS team
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!(
+        "cargo:rustc-link-arg-bins=-T{}/../src/app/elf/user.lds",
+        manifest_dir
+    );
+    println!("cargo:rerun-if-changed=../src/app/elf/user.lds");
+}
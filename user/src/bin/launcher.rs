@@ -0,0 +1,37 @@
+// launcher.rs
+// Starts other embedded programs with arguments and environment
+// tongOS team
+
+#![no_std]
+#![no_main]
+
+use tong_user::{abi, entry, env, println, process};
+
+fn run(name: &str, argv: &[&str], envp: &[&str]) {
+    match process::spawn_program(name, argv, envp) {
+        Ok(pid) => {
+            let code = process::join(pid).unwrap();
+            println!("launcher: {} (pid {}) exited with {}", name, pid, code);
+        }
+        Err(errno) => println!("launcher: could not spawn {}: {:?}", name, errno),
+    }
+}
+
+fn main() -> usize {
+    for (index, argument) in env::args().enumerate() {
+        println!("launcher: argv[{}] = {}", index, argument);
+    }
+    println!(
+        "launcher: page size {}, entry {:#x}",
+        env::aux(abi::AT_PAGESZ).unwrap_or(0),
+        env::aux(abi::AT_ENTRY).unwrap_or(0)
+    );
+
+    run("hello", &["hello", "from", "launcher"], &[]);
+    run("workers", &["workers"], &["WORKERS=2"]);
+    run("missing", &["missing"], &[]);
+    run("ps", &["ps"], &[]);
+    0
+}
+
+entry!(main);
@@ -0,0 +1,46 @@
+// ps.rs
+// Lists the processes in the system
+// tongOS team
+
+#![no_std]
+#![no_main]
+
+use tong_user::{abi, entry, println, process};
+
+fn main() -> usize {
+    let list = match process::process_list() {
+        Ok(list) => list,
+        Err(errno) => {
+            println!("ps: {:?}", errno);
+            return 1;
+        }
+    };
+    let now = process::time_now();
+
+    println!(
+        "{:>5} {:>5} {:<16} {:<8} {:>4} {:>3} {:>10} {:>10} {:>8}",
+        "PID", "PPID", "NAME", "STATE", "HART", "NI", "AGE", "CPU", "MEM"
+    );
+    for info in list {
+        let hart = if info.state == abi::PROCESS_STATE_RUNNING {
+            info.hart
+        } else {
+            info.previous_hart
+        };
+        println!(
+            "{:>5} {:>5} {:<16} {:<8} {:>4} {:>3} {:>10} {:>10} {:>7}K",
+            info.pid,
+            info.parent,
+            info.name(),
+            info.state_name(),
+            hart,
+            info.nice,
+            now.saturating_sub(info.created_at),
+            info.cpu_time,
+            info.memory / 1024
+        );
+    }
+    0
+}
+
+entry!(main);
@@ -0,0 +1,53 @@
+// workers.rs
+// Threads summing slices of a heap allocated vector. The number of threads
+// comes from the WORKERS environment variable.
+// tongOS team
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use tong_user::{entry, env, println, process};
+
+const DEFAULT_WORKERS: usize = 4;
+const NUMBERS_PER_WORKER: usize = 250;
+
+static mut NUMBERS: Vec<usize> = Vec::new();
+
+fn worker(index: usize) -> usize {
+    let numbers = unsafe { &NUMBERS };
+    let start = index * NUMBERS_PER_WORKER;
+    let sum = numbers[start..start + NUMBERS_PER_WORKER].iter().sum();
+    println!("worker {} (pid {}): sum {}", index, process::getpid(), sum);
+    sum
+}
+
+fn main() -> usize {
+    let workers = env::getenv("WORKERS")
+        .and_then(|workers| workers.parse().ok())
+        .unwrap_or(DEFAULT_WORKERS);
+    println!(
+        "workers: started as pid {} with {} workers",
+        process::getpid(),
+        workers
+    );
+    unsafe {
+        NUMBERS = (1..=workers * NUMBERS_PER_WORKER).collect();
+    }
+
+    let mut pids = Vec::new();
+    for index in 0..workers {
+        pids.push(process::spawn(worker, index).unwrap());
+    }
+
+    let mut total = 0;
+    for pid in pids {
+        total += process::join(pid).unwrap();
+    }
+    println!("workers: total {}", total);
+    0
+}
+
+entry!(main);
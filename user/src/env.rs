@@ -0,0 +1,76 @@
+// env.rs
+// Arguments, environment and auxiliary vector of the program
+// tongOS team
+
+// The strings live on the initial stack built by the kernel (see abi.rs),
+// they stay there for the whole life of the program.
+
+use crate::abi;
+
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = core::ptr::null();
+static mut ENVP: *const *const u8 = core::ptr::null();
+static mut AUXV: *const usize = core::ptr::null();
+
+// _start, before main
+pub(crate) fn init(argc: usize, argv: *const *const u8) {
+    unsafe {
+        ARGC = argc;
+        ARGV = argv;
+        // envp starts after the NULL ending argv
+        ENVP = argv.add(argc + 1);
+        let mut end = ENVP;
+        while !(*end).is_null() {
+            end = end.add(1);
+        }
+        AUXV = end.add(1) as *const usize;
+    }
+}
+
+// Strings built by the kernel are UTF-8
+unsafe fn c_str(pointer: *const u8) -> &'static str {
+    let mut len = 0;
+    while *pointer.add(len) != 0 {
+        len += 1;
+    }
+    let bytes = core::slice::from_raw_parts(pointer, len);
+    core::str::from_utf8(bytes).unwrap_or("")
+}
+
+pub fn args() -> impl Iterator<Item = &'static str> {
+    (0..unsafe { ARGC }).map(|index| unsafe { c_str(*ARGV.add(index)) })
+}
+
+// NAME=value strings
+pub fn vars() -> impl Iterator<Item = &'static str> {
+    let envp = unsafe { ENVP };
+    (0..)
+        .map(move |index| unsafe { *envp.add(index) })
+        .take_while(|pointer| !pointer.is_null())
+        .map(|pointer| unsafe { c_str(pointer) })
+}
+
+pub fn getenv(name: &str) -> Option<&'static str> {
+    vars().find_map(|variable| {
+        let mut parts = variable.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(variable_name), Some(value)) if variable_name == name => Some(value),
+            _ => None,
+        }
+    })
+}
+
+// Value of an abi::AT_* entry
+pub fn aux(kind: usize) -> Option<usize> {
+    let mut entry = unsafe { AUXV };
+    loop {
+        let (entry_kind, value) = unsafe { (*entry, *entry.add(1)) };
+        if entry_kind == abi::AT_NULL {
+            return None;
+        }
+        if entry_kind == kind {
+            return Some(value);
+        }
+        entry = unsafe { entry.add(2) };
+    }
+}
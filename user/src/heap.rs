@@ -0,0 +1,133 @@
+// heap.rs
+// User heap
+// tongOS team
+
+// First fit allocator over a fixed arena in .bss, the same scheme as the
+// kernel's kmem.rs: every chunk starts with a header holding its size and
+// a taken bit, free neighbours are merged on free. Threads share the heap
+// of their process.
+
+use crate::lock::Mutex;
+
+use core::alloc::{GlobalAlloc, Layout};
+
+pub const HEAP_SIZE: usize = 256 * 1024;
+
+// Chunks and their headers are 16-byte aligned
+const ALIGN: usize = 16;
+const TAKEN: usize = 1 << 63;
+
+#[repr(C, align(16))]
+struct Arena([u8; HEAP_SIZE]);
+
+static mut ARENA: Arena = Arena([0; HEAP_SIZE]);
+static mut HEAP_LOCK: Mutex = Mutex::new();
+
+#[repr(C, align(16))]
+struct ChunkHeader {
+    flags_size: usize,
+}
+
+impl ChunkHeader {
+    fn is_taken(&self) -> bool {
+        self.flags_size & TAKEN != 0
+    }
+
+    fn size(&self) -> usize {
+        self.flags_size & !TAKEN
+    }
+
+    fn set(&mut self, size: usize, taken: bool) {
+        self.flags_size = if taken { size | TAKEN } else { size };
+    }
+}
+
+fn heap_start() -> *mut ChunkHeader {
+    unsafe { ARENA.0.as_mut_ptr() as *mut ChunkHeader }
+}
+
+fn heap_end() -> *mut ChunkHeader {
+    unsafe { heap_start().cast::<u8>().add(HEAP_SIZE) as *mut ChunkHeader }
+}
+
+unsafe fn next_chunk(chunk: *mut ChunkHeader) -> *mut ChunkHeader {
+    chunk.cast::<u8>().add((*chunk).size()) as *mut ChunkHeader
+}
+
+// One free chunk spanning the arena, before main
+pub fn init() {
+    unsafe { (*heap_start()).set(HEAP_SIZE, false) }
+}
+
+unsafe fn allocate(size: usize) -> *mut u8 {
+    let size = (size + ALIGN - 1) / ALIGN * ALIGN + core::mem::size_of::<ChunkHeader>();
+    let mut chunk = heap_start();
+
+    while chunk < heap_end() {
+        let chunk_size = (*chunk).size();
+        if chunk_size == 0 {
+            // Corrupted heap
+            break;
+        }
+        if !(*chunk).is_taken() && size <= chunk_size {
+            let rest = chunk_size - size;
+            if rest > core::mem::size_of::<ChunkHeader>() {
+                (*chunk).set(size, true);
+                (*next_chunk(chunk)).set(rest, false);
+            } else {
+                (*chunk).set(chunk_size, true);
+            }
+            return chunk.add(1) as *mut u8;
+        }
+        chunk = next_chunk(chunk);
+    }
+    core::ptr::null_mut()
+}
+
+unsafe fn free(pointer: *mut u8) {
+    let chunk = (pointer as *mut ChunkHeader).sub(1);
+    (*chunk).set((*chunk).size(), false);
+
+    // Merge adjacent free chunks
+    let mut chunk = heap_start();
+    while chunk < heap_end() && (*chunk).size() != 0 {
+        let next = next_chunk(chunk);
+        if next < heap_end() && !(*chunk).is_taken() && !(*next).is_taken() {
+            (*chunk).set((*chunk).size() + (*next).size(), false);
+        } else {
+            chunk = next;
+        }
+    }
+}
+
+struct UserGlobalAlloc;
+
+unsafe impl GlobalAlloc for UserGlobalAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > ALIGN {
+            return core::ptr::null_mut();
+        }
+        HEAP_LOCK.spin_lock();
+        let pointer = allocate(layout.size());
+        HEAP_LOCK.unlock();
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, _layout: Layout) {
+        HEAP_LOCK.spin_lock();
+        free(pointer);
+        HEAP_LOCK.unlock();
+    }
+}
+
+#[global_allocator]
+static GA: UserGlobalAlloc = UserGlobalAlloc;
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "out of heap allocating {} bytes with {}-byte alignment",
+        layout.size(),
+        layout.align()
+    );
+}
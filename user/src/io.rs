@@ -0,0 +1,75 @@
+// io.rs
+// Console input and output
+// tongOS team
+
+// The kernel prints every print_str on its own line, so print! keeps the
+// text after the last newline until the line is complete.
+
+use crate::abi;
+use crate::lock::Mutex;
+use crate::syscall::syscall;
+
+use alloc::string::String;
+
+// Longest line read_line returns, the rest of a longer line is dropped
+pub const MAX_LINE: usize = 256;
+
+static mut STDOUT_LOCK: Mutex = Mutex::new();
+static mut STDOUT_LINE: String = String::new();
+
+// Prints text as one line
+pub fn print_str(text: &str) {
+    let _ = syscall(
+        abi::SYS_PRINT_STR,
+        [text.as_ptr() as usize, text.len(), 0, 0, 0],
+    );
+}
+
+// Blocks until a line is typed and appends it to buffer. Returns the
+// number of bytes read.
+pub fn read_line(buffer: &mut String) -> abi::SyscallResult {
+    let mut line = [0u8; MAX_LINE];
+    let len = syscall(
+        abi::SYS_READ_LINE,
+        [line.as_mut_ptr() as usize, line.len(), 0, 0, 0],
+    )?;
+    buffer.push_str(&String::from_utf8_lossy(&line[..len]));
+    Ok(len)
+}
+
+struct Stdout;
+
+impl core::fmt::Write for Stdout {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        let line = unsafe { &mut STDOUT_LINE };
+        let mut rest = text;
+        while let Some(newline) = rest.find('\n') {
+            line.push_str(&rest[..newline]);
+            print_str(line);
+            line.clear();
+            rest = &rest[newline + 1..];
+        }
+        line.push_str(rest);
+        Ok(())
+    }
+}
+
+pub fn _print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    let lock = unsafe { &mut STDOUT_LOCK };
+    lock.spin_lock();
+    let _ = Stdout.write_fmt(args);
+    lock.unlock();
+}
+
+// Prints what is left of an unfinished line, the runtime calls it on exit
+pub fn flush() {
+    let lock = unsafe { &mut STDOUT_LOCK };
+    lock.spin_lock();
+    let line = unsafe { &mut STDOUT_LINE };
+    if !line.is_empty() {
+        print_str(line);
+        line.clear();
+    }
+    lock.unlock();
+}
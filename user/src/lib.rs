@@ -0,0 +1,65 @@
+// lib.rs
+// tongOS user runtime
+// tongOS team
+
+// What a program needs to run as its own ELF binary in user mode: the
+// entry point, syscall wrappers, print!/println!, a heap and a panic
+// handler that exits. Programs are `#![no_std]` `#![no_main]` binaries that
+// name their main function with entry!, see src/bin.
+// Only the syscall ABI (abi.rs) is shared with the kernel.
+
+#![no_std]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(alloc_error_handler)]
+#![feature(panic_info_message)]
+
+extern crate alloc;
+
+// Names the function the runtime calls after setting up the process. It
+// returns the exit code.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        pub fn __tong_user_main() -> usize {
+            let main: fn() -> usize = $main;
+            main()
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($args:tt)+) => {{
+        $crate::io::_print(format_args!($($args)+))
+    }};
+}
+
+#[macro_export]
+macro_rules! println {
+    () => {{
+        $crate::print!("\n")
+    }};
+    ($fmt:expr) => {{
+        $crate::print!(concat!($fmt, "\n"))
+    }};
+    ($fmt:expr, $($args:tt)+) => {{
+        $crate::print!(concat!($fmt, "\n"), $($args)+)
+    }};
+}
+
+#[path = "../../src/abi.rs"]
+pub mod abi;
+#[path = "../../src/lock.rs"]
+pub mod lock;
+
+pub mod env;
+pub mod heap;
+pub mod io;
+pub mod process;
+pub mod signal;
+mod start;
+pub mod syscall;
+
+pub use abi::{Errno, SyscallResult};
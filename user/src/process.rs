@@ -0,0 +1,173 @@
+// process.rs
+// Processes and threads
+// tongOS team
+
+use crate::abi::{self, Errno, ProcessInfo, ResourceUsage, SyscallResult};
+use crate::syscall::syscall;
+
+use alloc::vec::Vec;
+
+pub fn exit(code: usize) -> ! {
+    let _ = syscall(abi::SYS_EXIT, [code, 0, 0, 0, 0]);
+    loop {}
+}
+
+pub fn getpid() -> usize {
+    syscall(abi::SYS_GETPID, [0; 5]).unwrap()
+}
+
+pub fn getppid() -> usize {
+    syscall(abi::SYS_GETPPID, [0; 5]).unwrap()
+}
+
+// Sleeps for amount context switch periods
+pub fn sleep(amount: usize) {
+    let _ = syscall(abi::SYS_SLEEP, [amount, 0, 0, 0, 0]);
+}
+
+// CPU time and scheduling counters of pid, 0 for the caller
+pub fn getrusage(pid: usize) -> Result<ResourceUsage, Errno> {
+    let mut usage = ResourceUsage::new();
+    let buffer = &mut usage as *mut ResourceUsage as usize;
+    syscall(abi::SYS_GETRUSAGE, [pid, buffer, 0, 0, 0])?;
+    Ok(usage)
+}
+
+// Sets the nice value of pid, 0 for the caller. The lower it is, between
+// abi::NICE_MIN and abi::NICE_MAX, the sooner and longer pid runs.
+pub fn nice(pid: usize, nice: isize) -> Result<(), Errno> {
+    syscall(abi::SYS_NICE, [pid, nice as usize, 0, 0, 0]).map(|_| ())
+}
+
+// Scheduling policy of the system, one of abi::SCHED_*
+pub fn sched_getpolicy() -> usize {
+    syscall(abi::SYS_SCHED_GETPOLICY, [0; 5]).unwrap()
+}
+
+// Returns the previous policy
+pub fn sched_setpolicy(policy: usize) -> SyscallResult {
+    syscall(abi::SYS_SCHED_SETPOLICY, [policy, 0, 0, 0, 0])
+}
+
+// Makes the caller a periodic real-time task, times in context switch
+// periods. Returns the hart it runs on, EBUSY if none can take it.
+pub fn sched_setperiodic(period: usize, wcet: usize, deadline: usize) -> SyscallResult {
+    syscall(abi::SYS_SCHED_SETPERIODIC, [period, wcet, deadline, 0, 0])
+}
+
+// Ends the current job and waits for the next period
+pub fn sched_wait_period() {
+    let _ = syscall(abi::SYS_SCHED_WAIT_PERIOD, [0; 5]);
+}
+
+// Idle time, context switches and steals of every hart
+pub fn sched_stats() -> Result<Vec<abi::HartStats>, Errno> {
+    let mut stats = Vec::new();
+    stats.resize(abi::MAX_HARTS, abi::HartStats::default());
+    let harts = syscall(
+        abi::SYS_SCHED_STATS,
+        [stats.as_mut_ptr() as usize, stats.len(), 0, 0, 0],
+    )?;
+    stats.truncate(harts);
+    Ok(stats)
+}
+
+// Restricts pid, 0 for the caller, to the harts in mask, bit n for hart n
+pub fn sched_setaffinity(pid: usize, mask: u64) -> Result<(), Errno> {
+    syscall(abi::SYS_SCHED_SETAFFINITY, [pid, mask as usize, 0, 0, 0]).map(|_| ())
+}
+
+pub fn sched_getaffinity(pid: usize) -> Result<u64, Errno> {
+    syscall(abi::SYS_SCHED_GETAFFINITY, [pid, 0, 0, 0, 0]).map(|mask| mask as u64)
+}
+
+pub fn time_now() -> usize {
+    syscall(abi::SYS_TIME_NOW, [0; 5]).unwrap()
+}
+
+// Threads start here, the kernel gives no return address
+extern "C" fn thread_start(function: usize, argument: usize) -> ! {
+    let function: fn(usize) -> usize = unsafe { core::mem::transmute(function) };
+    exit(function(argument))
+}
+
+// Runs function(argument) in a new thread of this process. Returns its pid,
+// the thread exits with what function returns.
+pub fn spawn(function: fn(usize) -> usize, argument: usize) -> SyscallResult {
+    syscall(
+        abi::SYS_CREATE_THREAD,
+        [thread_start as usize, function as usize, argument, 0, 0],
+    )
+}
+
+// Waits for pid to exit and returns its exit code,
+// ESRCH if there is no such process to join.
+pub fn join(pid: usize) -> SyscallResult {
+    syscall(abi::SYS_JOIN, [pid, 0, 0, 0, 0])
+}
+
+pub fn kill(pid: usize, signal: usize) -> Result<(), Errno> {
+    syscall(abi::SYS_KILL, [pid, signal, 0, 0, 0]).map(|_| ())
+}
+
+// Stops pid until it is resumed
+pub fn suspend(pid: usize) -> Result<(), Errno> {
+    kill(pid, abi::SIGSTOP)
+}
+
+pub fn resume(pid: usize) -> Result<(), Errno> {
+    kill(pid, abi::SIGCONT)
+}
+
+// NUL terminated copies of strings and the NULL terminated array pointing
+// at them
+fn c_strings(strings: &[&str]) -> (Vec<Vec<u8>>, Vec<usize>) {
+    let copies: Vec<Vec<u8>> = strings
+        .iter()
+        .map(|string| {
+            let mut copy = Vec::with_capacity(string.len() + 1);
+            copy.extend_from_slice(string.as_bytes());
+            copy.push(0);
+            copy
+        })
+        .collect();
+    let mut pointers: Vec<usize> = copies.iter().map(|copy| copy.as_ptr() as usize).collect();
+    pointers.push(0);
+    (copies, pointers)
+}
+
+// Starts the program embedded in the kernel as name, a child of this
+// process. envp holds NAME=value strings. Returns its pid.
+pub fn spawn_program(name: &str, argv: &[&str], envp: &[&str]) -> SyscallResult {
+    let (_argv_strings, argv) = c_strings(argv);
+    let (_envp_strings, envp) = c_strings(envp);
+    syscall(
+        abi::SYS_SPAWN,
+        [
+            name.as_ptr() as usize,
+            name.len(),
+            argv.as_ptr() as usize,
+            envp.as_ptr() as usize,
+            0,
+        ],
+    )
+}
+
+// Snapshot of every process in the system, ordered by pid
+pub fn process_list() -> Result<Vec<ProcessInfo>, Errno> {
+    let mut list = Vec::new();
+    loop {
+        // Room for a few processes created meanwhile
+        let capacity = list.capacity().max(list.len() + 8);
+        list.resize(capacity, ProcessInfo::empty());
+        let count = syscall(
+            abi::SYS_PROCESS_LIST,
+            [list.as_mut_ptr() as usize, capacity, 0, 0, 0],
+        )?;
+        if count <= capacity {
+            list.truncate(count);
+            return Ok(list);
+        }
+        list.resize(count, ProcessInfo::empty());
+    }
+}
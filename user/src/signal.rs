@@ -0,0 +1,38 @@
+// signal.rs
+// Signal handlers and mask
+// tongOS team
+
+use crate::abi::{self, Errno};
+use crate::syscall::syscall;
+
+pub use crate::abi::{
+    NSIG, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGHUP, SIGILL, SIGINT, SIGKILL,
+    SIGNAL_EXIT_BASE, SIGPIPE, SIGQUIT, SIGSEGV, SIGSTOP, SIGSYS, SIGTERM, SIGTRAP, SIGUSR1,
+    SIGUSR2, SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, SIG_UNBLOCK,
+};
+
+// Handlers return here, a0 = 11 is sigreturn
+global_asm!(
+    ".section .text",
+    ".global __tong_user_signal_restorer",
+    "__tong_user_signal_restorer:",
+    "li a0, 11",
+    "ecall",
+);
+
+extern "C" {
+    fn __tong_user_signal_restorer();
+}
+
+// Sets the action of signal for the calling thread: SIG_DFL, SIG_IGN or the
+// address of an `extern "C" fn(signal: usize)`.
+pub fn sigaction(signal: usize, handler: usize) -> Result<(), Errno> {
+    let restorer = __tong_user_signal_restorer as usize;
+    syscall(abi::SYS_SIGACTION, [signal, handler, restorer, 0, 0]).map(|_| ())
+}
+
+// Changes the blocked mask (SIG_BLOCK, SIG_UNBLOCK or SIG_SETMASK) and
+// returns the previous one.
+pub fn sigprocmask(how: usize, mask: u64) -> Result<u64, Errno> {
+    syscall(abi::SYS_SIGPROCMASK, [how, mask as usize, 0, 0, 0]).map(|old| old as u64)
+}
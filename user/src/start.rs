@@ -0,0 +1,56 @@
+// start.rs
+// Program entry and exit
+// tongOS team
+
+// The kernel starts a program at _start with sp at the top of its stack,
+// a0 = argc and a1 = argv (see Process::new_from_elf). _start sets up gp,
+// the heap and the environment and then runs the main function named with
+// entry!.
+
+use crate::env;
+use crate::heap;
+use crate::io;
+use crate::process;
+
+global_asm!(
+    ".section .text._start",
+    ".global _start",
+    "_start:",
+    ".option push",
+    ".option norelax",
+    "la gp, __global_pointer$",
+    ".option pop",
+    "call __tong_user_start",
+);
+
+extern "Rust" {
+    fn __tong_user_main() -> usize;
+}
+
+#[no_mangle]
+extern "C" fn __tong_user_start(argc: usize, argv: *const *const u8) -> ! {
+    heap::init();
+    env::init(argc, argv);
+    let code = unsafe { __tong_user_main() };
+    io::flush();
+    process::exit(code)
+}
+
+// Like Rust on other systems, a panicking program exits with 101
+const PANIC_EXIT_CODE: usize = 101;
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    io::flush();
+    match info.location() {
+        Some(location) => println!(
+            "pid {} panicked at {}:{}: {}",
+            process::getpid(),
+            location.file(),
+            location.line(),
+            info.message().unwrap()
+        ),
+        None => println!("pid {} panicked", process::getpid()),
+    }
+    process::exit(PANIC_EXIT_CODE)
+}
@@ -0,0 +1,23 @@
+// syscall.rs
+// Raw system calls
+// tongOS team
+
+use crate::abi::{self, SyscallResult};
+
+// ecall with the ABI of abi.rs: number in a0, arguments in a1-a5 and the
+// encoded result back in a0
+pub fn syscall(number: usize, args: [usize; 5]) -> SyscallResult {
+    let a0: usize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") number => a0,
+            in("a1") args[0],
+            in("a2") args[1],
+            in("a3") args[2],
+            in("a4") args[3],
+            in("a5") args[4],
+        );
+    }
+    abi::decode(a0)
+}